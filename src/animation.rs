@@ -0,0 +1,286 @@
+//! Animated sources.
+//!
+//! `ImageReader::decode` only yields the first frame of an animated GIF or
+//! WebP. Animations are decoded into full-canvas frames instead, so that the
+//! same geometry can be applied to every frame.
+
+use crate::riff;
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, ImageFormat, RgbaImage};
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// Selects a single frame of an animation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameSelector {
+    Index(usize),
+    Middle,
+    Last,
+}
+
+impl FrameSelector {
+    /// The index of the selected frame in an animation of `frames` frames, or
+    /// `None` if it's out of range. Still images have a single frame.
+    pub fn index(&self, frames: usize) -> Option<usize> {
+        match self {
+            Self::Index(index) if *index < frames => Some(*index),
+            Self::Index(_) => None,
+            Self::Middle => Some(frames / 2),
+            Self::Last => frames.checked_sub(1),
+        }
+    }
+}
+
+impl FromStr for FrameSelector {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<FrameSelector, Self::Err> {
+        match input {
+            "middle" => Ok(Self::Middle),
+            "last" => Ok(Self::Last),
+            _ => input.parse().map(Self::Index).map_err(|_| ()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub image: RgbaImage,
+    // How long the frame is shown, in milliseconds
+    pub delay: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    // Number of times the animation is played, 0 meaning forever
+    pub loop_count: u32,
+}
+
+impl Animation {
+    /// Applies `f` to every frame, keeping the delays and loop count.
+    pub fn map(&self, f: impl Fn(&RgbaImage) -> RgbaImage) -> Self {
+        Self {
+            frames: self
+                .frames
+                .iter()
+                .map(|frame| Frame {
+                    image: f(&frame.image),
+                    delay: frame.delay,
+                })
+                .collect(),
+            loop_count: self.loop_count,
+        }
+    }
+}
+
+/// Decodes all frames of an animated GIF or WebP source. Returns `None` if
+/// the source isn't a GIF or WebP or has a single frame only.
+pub fn decode(source: &[u8]) -> Option<Animation> {
+    let animation = match image::guess_format(source).ok()? {
+        ImageFormat::Gif => decode_gif(source)?,
+        ImageFormat::WebP => decode_webp(source)?,
+        _ => return None,
+    };
+    if animation.frames.len() < 2 {
+        return None;
+    }
+    Some(animation)
+}
+
+/// The number of frames in `source`, read from the frame headers without
+/// decoding any pixels. Sources other than GIF and WebP have a single frame.
+pub fn frame_count(source: &[u8]) -> usize {
+    let frames = match image::guess_format(source) {
+        Ok(ImageFormat::Gif) => gif_frame_count(source),
+        Ok(ImageFormat::WebP) => webp_frame_count(source),
+        _ => 1,
+    };
+    frames.max(1)
+}
+
+fn gif_frame_count(source: &[u8]) -> usize {
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let Ok(mut decoder) = options.read_info(Cursor::new(source)) else {
+        return 1;
+    };
+    let mut count = 0;
+    while let Ok(Some(_)) = decoder.next_frame_info() {
+        count += 1;
+    }
+    count
+}
+
+// Counts the ANMF chunks of the RIFF container. Still WebPs have none.
+fn webp_frame_count(source: &[u8]) -> usize {
+    riff::chunks(source)
+        .filter(|chunk| &chunk.fourcc == b"ANMF")
+        .count()
+}
+
+fn decode_gif(source: &[u8]) -> Option<Animation> {
+    let frames = GifDecoder::new(Cursor::new(source))
+        .ok()?
+        .into_frames()
+        .collect_frames()
+        .ok()?;
+    // The `image` crate doesn't expose the loop count
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let decoder = options.read_info(Cursor::new(source)).ok()?;
+    let loop_count = match decoder.repeat() {
+        gif::Repeat::Infinite => 0,
+        gif::Repeat::Finite(n) => n as u32,
+    };
+    Some(Animation {
+        frames: frames
+            .into_iter()
+            .map(|frame| {
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                Frame {
+                    delay: numer / denom.max(1),
+                    image: frame.into_buffer(),
+                }
+            })
+            .collect(),
+        loop_count,
+    })
+}
+
+fn decode_webp(source: &[u8]) -> Option<Animation> {
+    let decoded = webp::AnimDecoder::new(source).decode().ok()?;
+    let mut frames = Vec::with_capacity(decoded.len());
+    // Timestamps mark the end of each frame
+    let mut start = 0;
+    for index in 0..decoded.len() {
+        let frame = decoded.get_frame(index)?;
+        let image = match frame.get_layout() {
+            webp::PixelLayout::Rgba => {
+                RgbaImage::from_raw(frame.width(), frame.height(), frame.get_image().to_vec())?
+            }
+            webp::PixelLayout::Rgb => image::DynamicImage::ImageRgb8(image::RgbImage::from_raw(
+                frame.width(),
+                frame.height(),
+                frame.get_image().to_vec(),
+            )?)
+            .into_rgba8(),
+        };
+        let end = frame.get_time_ms().max(start);
+        frames.push(Frame {
+            image,
+            delay: (end - start) as u32,
+        });
+        start = end;
+    }
+    Some(Animation {
+        frames,
+        loop_count: decoded.loop_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn gif(loop_count: gif::Repeat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut bytes, 4, 4, &[]).unwrap();
+            encoder.set_repeat(loop_count).unwrap();
+            for (i, delay) in [10, 20, 30].into_iter().enumerate() {
+                let mut pixels = vec![0; 4 * 4 * 4];
+                pixels[0] = 80 * i as u8;
+                pixels[3] = 255;
+                let mut frame = gif::Frame::from_rgba(4, 4, &mut pixels);
+                frame.delay = delay;
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_frame_selector_from_str() {
+        assert_eq!(FrameSelector::from_str("3"), Ok(FrameSelector::Index(3)));
+        assert_eq!(FrameSelector::from_str("middle"), Ok(FrameSelector::Middle));
+        assert_eq!(FrameSelector::from_str("last"), Ok(FrameSelector::Last));
+        assert_eq!(FrameSelector::from_str("-1"), Err(()));
+        assert_eq!(FrameSelector::from_str("first"), Err(()));
+    }
+
+    #[test]
+    fn test_frame_selector_index() {
+        assert_eq!(FrameSelector::Index(2).index(3), Some(2));
+        assert_eq!(FrameSelector::Index(3).index(3), None);
+        assert_eq!(FrameSelector::Middle.index(4), Some(2));
+        assert_eq!(FrameSelector::Middle.index(1), Some(0));
+        assert_eq!(FrameSelector::Last.index(3), Some(2));
+    }
+
+    #[test]
+    fn test_decode_gif() {
+        let animation = decode(&gif(gif::Repeat::Finite(3))).unwrap();
+        assert_eq!(animation.loop_count, 3);
+        let delays: Vec<u32> = animation.frames.iter().map(|f| f.delay).collect();
+        assert_eq!(delays, vec![100, 200, 300]);
+        assert_eq!(
+            animation.frames[2].image.get_pixel(0, 0).0,
+            [160, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn test_decode_gif_loops_forever() {
+        let animation = decode(&gif(gif::Repeat::Infinite)).unwrap();
+        assert_eq!(animation.loop_count, 0);
+    }
+
+    #[test]
+    fn test_decode_still_image() {
+        let mut png = Vec::new();
+        RgbaImage::new(1, 1)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert!(decode(&png).is_none());
+    }
+
+    #[test]
+    fn test_frame_count_gif() {
+        assert_eq!(frame_count(&gif(gif::Repeat::Infinite)), 3);
+    }
+
+    #[test]
+    fn test_frame_count_webp() {
+        let config = webp::WebPConfig::new().unwrap();
+        // Identical frames would be merged
+        let frames = [[0; 4 * 4 * 4], [255; 4 * 4 * 4]];
+        let mut encoder = webp::AnimEncoder::new(4, 4, &config);
+        for (timestamp, pixels) in [0, 100].into_iter().zip(&frames) {
+            encoder.add_frame(webp::AnimFrame::from_rgba(pixels, 4, 4, timestamp));
+        }
+        let animated = encoder.encode();
+        assert_eq!(frame_count(&animated), 2);
+        let still = webp::Encoder::from_rgba(&frames[0], 4, 4).encode_lossless();
+        assert_eq!(frame_count(&still), 1);
+    }
+
+    #[test]
+    fn test_frame_count_still_image() {
+        let mut png = Vec::new();
+        RgbaImage::new(1, 1)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert_eq!(frame_count(&png), 1);
+    }
+
+    #[test]
+    fn test_map_keeps_timing() {
+        let animation = decode(&gif(gif::Repeat::Finite(2))).unwrap();
+        let mapped = animation.map(|_| RgbaImage::from_pixel(2, 2, Rgba([1, 2, 3, 4])));
+        assert_eq!(mapped.loop_count, 2);
+        assert_eq!(mapped.frames[1].delay, 200);
+        assert_eq!(mapped.frames[1].image.dimensions(), (2, 2));
+    }
+}
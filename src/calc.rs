@@ -26,7 +26,7 @@ impl RelativePoint {
     pub fn build(x: f64, y: f64) -> Result<Self, ValidationErr> {
         for e in [x, y] {
             match e {
-                e if e < 0. || e > 100. => return Err(ValidationErr),
+                e if !(0. ..=100.).contains(&e) => return Err(ValidationErr),
                 _ => (),
             }
         }
@@ -108,7 +108,7 @@ pub fn true_focal_point(image_length: u32, crop_length: u32, focal_point: f64) -
 }
 
 fn true_focal_point_rel(focal_point: f64, space: f64) -> f64 {
-    (focal_point.min(100.).max(0.) - 50.)
+    (focal_point.clamp(0., 100.) - 50.)
         .max((-space).min(0.))
         .min(space.max(0.))
         + 50.
@@ -126,8 +126,8 @@ fn crop_box(image_box: &Box, crop_box: &Box, focal_point: &RelativePoint) -> Cro
     // percentage, returns the crop coordinates as pixels relative to the image's
     // dimensions.
     let true_focal_point = Point {
-        x: true_focal_point(image_box.w, crop_box.w, focal_point.x) as u32,
-        y: true_focal_point(image_box.h, crop_box.h, focal_point.y) as u32,
+        x: true_focal_point(image_box.w, crop_box.w, focal_point.x),
+        y: true_focal_point(image_box.h, crop_box.h, focal_point.y),
     };
     // TODO: too much casting going on, find other way to ensure positive (unsigned) values
     CropBox {
@@ -181,9 +181,9 @@ pub fn fit(
     focal_point: &RelativePoint,
     zoom: &Option<f64>,
 ) -> (Box, CropBox) {
-    let resize_box = add_missing_edge(&image_box, &resize_box);
-    let resized_and_zoomed = resize_and_zoom(&image_box, &resize_box, &zoom);
-    let cropped = crop_box(&resized_and_zoomed, &resize_box, &focal_point);
+    let resize_box = add_missing_edge(image_box, resize_box);
+    let resized_and_zoomed = resize_and_zoom(image_box, &resize_box, zoom);
+    let cropped = crop_box(&resized_and_zoomed, &resize_box, focal_point);
     (resized_and_zoomed, cropped)
 }
 
@@ -194,11 +194,51 @@ pub fn crop(
     focal_point: &RelativePoint,
     zoom: &Option<f64>,
 ) -> (Box, CropBox) {
-    let resized_and_zoomed = crop_and_zoom(&image_box, &resize_box, &zoom);
-    let cropped = crop_box(&resized_and_zoomed, &resize_box, &focal_point);
+    let resized_and_zoomed = crop_and_zoom(image_box, resize_box, zoom);
+    let cropped = crop_box(&resized_and_zoomed, resize_box, focal_point);
     (resized_and_zoomed, cropped)
 }
 
+/// Scales a resize and crop tuple down, so that the crop is at most `max`
+/// pixels wide and high. Used to compute previews of the final image, such as
+/// placeholders, without resizing to its full size. Tuples that already fit
+/// are left as is.
+///
+/// # Examples
+///
+/// ```
+/// use imgconv::calc::{scale_down, Box, CropBox};
+/// let geometry = (
+///     Box { w: 1000, h: 500 },
+///     CropBox { top: 100, left: 0, bottom: 900, right: 400 },
+/// );
+/// let (resize_box, crop_box) = scale_down(&geometry, 80);
+/// assert_eq!(resize_box, Box { w: 100, h: 50 });
+/// assert_eq!(crop_box, CropBox { top: 10, left: 0, bottom: 90, right: 40 });
+/// ```
+pub fn scale_down((resize_box, crop_box): &(Box, CropBox), max: u32) -> (Box, CropBox) {
+    // As in `imageops::crop`, `top` and `bottom` are horizontal offsets
+    let (cw, ch) = (
+        crop_box.bottom - crop_box.top,
+        crop_box.right - crop_box.left,
+    );
+    let factor = (max as f64 / cw.max(ch).max(1) as f64).min(1.);
+    let scale = |v: u32| (v as f64 * factor).round() as u32;
+    let w = scale(resize_box.w).max(1);
+    let h = scale(resize_box.h).max(1);
+    let top = scale(crop_box.top).min(w - 1);
+    let left = scale(crop_box.left).min(h - 1);
+    (
+        Box { w, h },
+        CropBox {
+            top,
+            left,
+            bottom: scale(crop_box.bottom).clamp(top + 1, w),
+            right: scale(crop_box.right).clamp(left + 1, h),
+        },
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -230,7 +270,7 @@ mod tests {
     fn test_true_focal_point_rel() {
         assert_eq!(
             true_focal_point_rel(0., 8.333333333333334),
-            41.6666666666666664,
+            41.666_666_666_666_664,
         );
     }
 
@@ -565,4 +605,34 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_scale_down_keeps_small_geometry() {
+        let geometry = (
+            Box { w: 40, h: 30 },
+            CropBox {
+                top: 5,
+                left: 0,
+                bottom: 35,
+                right: 30,
+            },
+        );
+        assert_eq!(scale_down(&geometry, 64), geometry);
+    }
+
+    #[test]
+    fn test_scale_down_keeps_at_least_a_pixel() {
+        let geometry = (
+            Box { w: 4000, h: 10 },
+            CropBox {
+                top: 0,
+                left: 0,
+                bottom: 4000,
+                right: 10,
+            },
+        );
+        let (resize_box, crop_box) = scale_down(&geometry, 32);
+        assert_eq!(resize_box, Box { w: 32, h: 1 });
+        assert_eq!(crop_box.right - crop_box.left, 1);
+    }
 }
@@ -0,0 +1,450 @@
+//! Color management.
+//!
+//! Browsers that ignore embedded ICC profiles render every image as sRGB, so
+//! images with another profile (e.g. Display P3, Adobe RGB or CMYK) are
+//! converted to sRGB before anything else happens to them.
+//!
+//! This module also composites transparent images onto a background color,
+//! for media types without an alpha channel.
+
+use crate::resize;
+use image::{
+    DynamicImage, GenericImageView, ImageBuffer, ImageFormat, Pixel, RgbImage, Rgba, RgbaImage,
+};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformExecutor, TransformOptions};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// An sRGB color, written as six hex digits (e.g. `ffffff`) in query
+/// parameters and settings.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color(pub [u8; 3]);
+
+impl Color {
+    pub const WHITE: Self = Self([255, 255, 255]);
+}
+
+impl FromStr for Color {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Color, Self::Err> {
+        if input.len() != 6 || !input.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(());
+        }
+        let channel = |i: usize| u8::from_str_radix(&input[i..i + 2], 16).map_err(|_| ());
+        Ok(Self([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> String {
+        color.to_string()
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = &'static str;
+
+    fn try_from(input: String) -> Result<Color, Self::Error> {
+        Color::from_str(&input).map_err(|_| "color must be six hex digits")
+    }
+}
+
+/// Composites `image` onto `background`, making every pixel opaque.
+///
+/// # Examples
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use imgconv::color::{flatten, Color};
+/// let image = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 0]));
+/// assert_eq!(flatten(&image, Color::WHITE).get_pixel(0, 0).0, [255, 255, 255, 255]);
+/// ```
+pub fn flatten(image: &RgbaImage, background: Color) -> RgbaImage {
+    let mut flattened = image.clone();
+    for pixel in flattened.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        let blend =
+            |fg: u8, bg: u8| ((fg as u32 * alpha + bg as u32 * (255 - alpha) + 127) / 255) as u8;
+        *pixel = Rgba([
+            blend(pixel[0], background.0[0]),
+            blend(pixel[1], background.0[1]),
+            blend(pixel[2], background.0[2]),
+            u8::MAX,
+        ]);
+    }
+    flattened
+}
+
+/// Decodes `source` and converts its pixels from `icc_profile` to sRGB.
+///
+/// Returns `None` if the profile can't be parsed or isn't an RGB, grayscale or
+/// CMYK profile, in which case the source should be decoded as is.
+pub fn decode_srgb(source: &[u8], icc_profile: &[u8]) -> Option<DynamicImage> {
+    let profile = ColorProfile::new_from_slice(icc_profile).ok()?;
+    let srgb = ColorProfile::new_srgb();
+    let options = TransformOptions::default();
+    match profile.color_space {
+        DataColorSpace::Rgb => {
+            let image = image::load_from_memory(source).ok()?;
+            let layout = if image.color().has_alpha() {
+                Layout::Rgba
+            } else {
+                Layout::Rgb
+            };
+            // Sources with more than 8 bits per channel keep 16 bits
+            let image = match (layout, resize::is_high_bit_depth(&image)) {
+                (Layout::Rgba, false) => {
+                    let transform = profile
+                        .create_transform_8bit(layout, &srgb, layout, options)
+                        .ok()?;
+                    DynamicImage::ImageRgba8(convert(image.to_rgba8(), &*transform)?)
+                }
+                (Layout::Rgba, true) => {
+                    let transform = profile
+                        .create_transform_16bit(layout, &srgb, layout, options)
+                        .ok()?;
+                    DynamicImage::ImageRgba16(convert(image.to_rgba16(), &*transform)?)
+                }
+                (_, false) => {
+                    let transform = profile
+                        .create_transform_8bit(layout, &srgb, layout, options)
+                        .ok()?;
+                    DynamicImage::ImageRgb8(convert(image.to_rgb8(), &*transform)?)
+                }
+                (_, true) => {
+                    let transform = profile
+                        .create_transform_16bit(layout, &srgb, layout, options)
+                        .ok()?;
+                    DynamicImage::ImageRgb16(convert(image.to_rgb16(), &*transform)?)
+                }
+            };
+            Some(image)
+        }
+        DataColorSpace::Gray => {
+            let image = image::load_from_memory(source).ok()?;
+            if image.color().has_alpha() {
+                return None;
+            }
+            let (width, height) = image.dimensions();
+            if resize::is_high_bit_depth(&image) {
+                let transform = profile
+                    .create_transform_16bit(Layout::Gray, &srgb, Layout::Rgb, options)
+                    .ok()?;
+                let mut rgb = ImageBuffer::new(width, height);
+                transform.transform(&image.to_luma16(), &mut rgb).ok()?;
+                return Some(DynamicImage::ImageRgb16(rgb));
+            }
+            let transform = profile
+                .create_transform_8bit(Layout::Gray, &srgb, Layout::Rgb, options)
+                .ok()?;
+            let mut rgb = RgbImage::new(width, height);
+            transform.transform(&image.to_luma8(), &mut rgb).ok()?;
+            Some(DynamicImage::ImageRgb8(rgb))
+        }
+        DataColorSpace::Cmyk => {
+            let (cmyk, width, height) = decode_cmyk(source)?;
+            // CMYK uses the same layout as RGBA
+            let transform = profile
+                .create_transform_8bit(Layout::Rgba, &srgb, Layout::Rgb, options)
+                .ok()?;
+            let mut rgb = RgbImage::new(width, height);
+            transform.transform(&cmyk, &mut rgb).ok()?;
+            Some(DynamicImage::ImageRgb8(rgb))
+        }
+        _ => None,
+    }
+}
+
+/// Converts the pixels of `image` from the RGB `icc_profile` to sRGB, e.g.
+/// for the frames of an animation. Returns `None` if the profile can't be
+/// parsed or isn't an RGB profile.
+pub fn convert_srgb(image: &RgbaImage, icc_profile: &[u8]) -> Option<RgbaImage> {
+    let profile = ColorProfile::new_from_slice(icc_profile).ok()?;
+    if profile.color_space != DataColorSpace::Rgb {
+        return None;
+    }
+    let transform = profile
+        .create_transform_8bit(
+            Layout::Rgba,
+            &ColorProfile::new_srgb(),
+            Layout::Rgba,
+            TransformOptions::default(),
+        )
+        .ok()?;
+    convert(image.clone(), &*transform)
+}
+
+// Applies `transform` to the pixels of `image`, which keep their layout
+fn convert<P: Pixel>(
+    mut image: ImageBuffer<P, Vec<P::Subpixel>>,
+    transform: &dyn TransformExecutor<P::Subpixel>,
+) -> Option<ImageBuffer<P, Vec<P::Subpixel>>>
+where
+    P::Subpixel: Default,
+{
+    let pixels = image.to_vec();
+    transform.transform(&pixels, &mut image).ok()?;
+    Some(image)
+}
+
+// Decodes a CMYK JPEG to its (non-inverted) CMYK values. The `image` crate
+// only offers a naive conversion to RGB that ignores the profile.
+fn decode_cmyk(source: &[u8]) -> Option<(Vec<u8>, u32, u32)> {
+    if image::guess_format(source).ok()? != ImageFormat::Jpeg {
+        return None;
+    }
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(source));
+    let pixels = decoder.decode().ok()?;
+    let info = decoder.info()?;
+    if info.pixel_format != jpeg_decoder::PixelFormat::CMYK32 {
+        return None;
+    }
+    Some((pixels, info.width as u32, info.height as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma, Rgb};
+
+    // Compares conversions without depending on exact rounding
+    fn close(a: &[u8], b: &[u8]) -> bool {
+        a.iter().zip(b).all(|(a, b)| a.abs_diff(*b) <= 2)
+    }
+
+    fn png(image: &DynamicImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_decode_srgb_display_p3() {
+        let p3 = ColorProfile::new_display_p3().encode().unwrap();
+        let source = png(&DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            2,
+            2,
+            Rgb([255, 0, 0]),
+        )));
+        let converted = decode_srgb(&source, &p3).unwrap().to_rgb8();
+        // P3 red lies outside of sRGB, so it's clipped to the sRGB primary
+        assert!(close(&converted.get_pixel(0, 0).0, &[255, 0, 0]));
+        let source = png(&DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            2,
+            2,
+            Rgb([128, 128, 128]),
+        )));
+        let converted = decode_srgb(&source, &p3).unwrap().to_rgb8();
+        // Both share the sRGB transfer function and D65 white point
+        assert!(close(&converted.get_pixel(0, 0).0, &[128, 128, 128]));
+    }
+
+    #[test]
+    fn test_decode_srgb_adobe_rgb() {
+        let adobe_rgb = ColorProfile::new_adobe_rgb().encode().unwrap();
+        let source = png(&DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            1,
+            1,
+            Rgb([100, 150, 100]),
+        )));
+        let converted = decode_srgb(&source, &adobe_rgb).unwrap().to_rgb8();
+        let [r, g, b] = converted.get_pixel(0, 0).0;
+        // The same values are more saturated in Adobe RGB than in sRGB
+        assert!(g > 150 && r < 100 && b < 100);
+    }
+
+    #[test]
+    fn test_decode_srgb_keeps_alpha() {
+        let p3 = ColorProfile::new_display_p3().encode().unwrap();
+        let source = png(&DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            1,
+            1,
+            Rgba([10, 20, 30, 40]),
+        )));
+        let converted = decode_srgb(&source, &p3).unwrap();
+        assert_eq!(converted.to_rgba8().get_pixel(0, 0)[3], 40);
+    }
+
+    #[test]
+    fn test_decode_srgb_gray() {
+        let gray = ColorProfile::new_gray_with_gamma(2.2).encode().unwrap();
+        let source = png(&DynamicImage::ImageLuma8(GrayImage::from_pixel(
+            1,
+            1,
+            Luma([0]),
+        )));
+        let converted = decode_srgb(&source, &gray).unwrap();
+        assert_eq!(converted.to_rgb8().get_pixel(0, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_srgb_keeps_16_bit() {
+        use crate::metadata::{self, Embedded, Metadata};
+        // A 16-bit PNG with an embedded Display P3 profile, in a shade of gray
+        // that 8 bits per channel can't represent
+        let source = metadata::embed(
+            png(&DynamicImage::ImageRgba16(ImageBuffer::from_pixel(
+                1,
+                1,
+                Rgba([1100, 1100, 1100, 65535]),
+            ))),
+            &Embedded {
+                icc_profile: Some(ColorProfile::new_display_p3().encode().unwrap()),
+                exif: None,
+            },
+        );
+        let icc_profile = metadata::read(&source, Metadata::Strip)
+            .icc_profile
+            .unwrap();
+        let converted = decode_srgb(&source, &icc_profile).unwrap();
+        assert_eq!(converted.color(), image::ColorType::Rgba16);
+        let [r, g, b, a] = converted.to_rgba16().get_pixel(0, 0).0;
+        for channel in [r, g, b] {
+            assert!(channel.abs_diff(1100) < 20, "{}", channel);
+        }
+        assert_eq!(a, 65535);
+    }
+
+    #[test]
+    fn test_decode_srgb_gray_keeps_16_bit() {
+        let gray = ColorProfile::new_gray_with_gamma(2.2).encode().unwrap();
+        let source = png(&DynamicImage::ImageLuma16(ImageBuffer::from_pixel(
+            1,
+            1,
+            Luma([65535u16]),
+        )));
+        let converted = decode_srgb(&source, &gray).unwrap();
+        assert_eq!(converted.color(), image::ColorType::Rgb16);
+        assert_eq!(converted.to_rgb16().get_pixel(0, 0).0, [65535; 3]);
+    }
+
+    // A CMYK profile that maps ink combinations to the sRGB colors they're
+    // named after, as D50 L*a*b* values
+    fn cmyk_profile() -> Vec<u8> {
+        use moxcms::{LutDataType, LutStore, LutType, LutWarehouse, Matrix3d, ProfileClass};
+        let lab = |c: u16, m: u16, y: u16, k: u16| match (c, m, y, k) {
+            (_, _, _, 1) | (1, 1, 1, _) => [0., 0., 0.],
+            (0, 0, 0, _) => [100., 0., 0.],
+            (1, 0, 0, _) => [90.67, -50.66, -14.96],
+            (0, 1, 0, _) => [60.17, 93.55, -60.5],
+            (0, 0, 1, _) => [97.61, -15.75, 93.39],
+            (0, 1, 1, _) => [54.29, 80.8, 69.89],
+            (1, 0, 1, _) => [87.82, -79.29, 80.99],
+            _ => [29.57, 68.3, -112.03],
+        };
+        // The legacy 16-bit L*a*b* encoding of lut16Type
+        let mut clut = Vec::new();
+        for c in 0..2 {
+            for m in 0..2 {
+                for y in 0..2 {
+                    for k in 0..2 {
+                        let [l, a, b]: [f64; 3] = lab(c, m, y, k);
+                        clut.push((l / 100. * 65280.).round() as u16);
+                        clut.push(((a + 128.) * 256.).round() as u16);
+                        clut.push(((b + 128.) * 256.).round() as u16);
+                    }
+                }
+            }
+        }
+        let lut = LutWarehouse::Lut(LutDataType {
+            num_input_channels: 4,
+            num_output_channels: 3,
+            num_clut_grid_points: 2,
+            matrix: Matrix3d::IDENTITY,
+            num_input_table_entries: 2,
+            num_output_table_entries: 2,
+            input_table: LutStore::Store16([0, 65535].repeat(4)),
+            clut_table: LutStore::Store16(clut),
+            output_table: LutStore::Store16([0, 65535].repeat(3)),
+            lut_type: LutType::Lut16,
+        });
+        let mut profile = ColorProfile::default();
+        profile.color_space = DataColorSpace::Cmyk;
+        profile.pcs = DataColorSpace::Lab;
+        profile.profile_class = ProfileClass::ColorSpace;
+        profile.white_point = moxcms::Chromaticity::D50.to_xyzd();
+        profile.lut_a_to_b_perceptual = Some(lut.clone());
+        profile.lut_a_to_b_colorimetric = Some(lut);
+        profile.encode().unwrap()
+    }
+
+    #[test]
+    fn test_decode_srgb_cmyk() {
+        // Cyan, magenta, no ink and black, in non-inverted CMYK values
+        let cmyk = [[255, 0, 0, 0], [0, 255, 0, 0], [0, 0, 0, 0], [0, 0, 0, 255]];
+        let profile = cmyk_profile();
+        let mut source = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut source, 100);
+        encoder.add_icc_profile(&profile).unwrap();
+        // Written as Adobe (inverted) CMYK, which the decoder inverts again
+        encoder
+            .encode(&cmyk.concat(), 4, 1, jpeg_encoder::ColorType::Cmyk)
+            .unwrap();
+        let converted = decode_srgb(&source, &profile).unwrap().to_rgb8();
+        let pixel = |x| converted.get_pixel(x, 0).0;
+        let [r, g, b] = pixel(0);
+        assert!(r < 80 && g > 200 && b > 200, "{:?}", pixel(0));
+        let [r, g, b] = pixel(1);
+        assert!(r > 200 && g < 80 && b > 200, "{:?}", pixel(1));
+        assert!(close(&pixel(2), &[255, 255, 255]), "{:?}", pixel(2));
+        assert!(pixel(3).iter().all(|v| *v < 40), "{:?}", pixel(3));
+    }
+
+    #[test]
+    fn test_convert_srgb() {
+        let adobe_rgb = ColorProfile::new_adobe_rgb().encode().unwrap();
+        let image = RgbaImage::from_pixel(1, 1, Rgba([100, 150, 100, 40]));
+        let converted = convert_srgb(&image, &adobe_rgb).unwrap();
+        let [r, g, b, a] = converted.get_pixel(0, 0).0;
+        assert!(g > 150 && r < 100 && b < 100);
+        assert_eq!(a, 40);
+        let gray = ColorProfile::new_gray_with_gamma(2.2).encode().unwrap();
+        assert!(convert_srgb(&image, &gray).is_none());
+    }
+
+    #[test]
+    fn test_decode_srgb_invalid_profile() {
+        let source = png(&DynamicImage::ImageRgb8(RgbImage::new(1, 1)));
+        assert!(decode_srgb(&source, b"profile").is_none());
+    }
+
+    #[test]
+    fn test_color_from_str() {
+        assert_eq!(Color::from_str("ff8000"), Ok(Color([255, 128, 0])));
+        assert_eq!(Color::from_str("FF8000"), Ok(Color([255, 128, 0])));
+        assert_eq!(Color::from_str("fff"), Err(()));
+        assert_eq!(Color::from_str("+f8000"), Err(()));
+        assert_eq!(Color::from_str("ffééff"), Err(()));
+    }
+
+    #[test]
+    fn test_color_to_string() {
+        assert_eq!(Color([255, 8, 0]).to_string(), "ff0800");
+        assert_eq!(
+            Color::from_str(&Color([1, 2, 3]).to_string()),
+            Ok(Color([1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn test_flatten() {
+        let image = RgbaImage::from_fn(3, 1, |x, _| Rgba([0, 0, 255, [0, 128, 255][x as usize]]));
+        let flattened = flatten(&image, Color([255, 0, 0]));
+        assert_eq!(flattened.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(flattened.get_pixel(1, 0).0, [127, 0, 128, 255]);
+        assert_eq!(flattened.get_pixel(2, 0).0, [0, 0, 255, 255]);
+    }
+}
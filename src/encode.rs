@@ -0,0 +1,956 @@
+//! Encoding of the transcoded image into the requested media type.
+
+use crate::animation::Animation;
+use crate::media_type::MediaType;
+use crate::metadata::{self, Embedded};
+use crate::quantize::{self, Indexed};
+use crate::resize::{self, Rgba16Image};
+use crate::riff;
+use crate::ssim;
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::error::{
+    EncodingError, ImageFormatHint, LimitError, LimitErrorKind, UnsupportedError,
+    UnsupportedErrorKind,
+};
+use image::{
+    ColorType, DynamicImage, ImageEncoder, ImageError, ImageFormat, ImageResult, RgbImage,
+    RgbaImage,
+};
+use jpeg_encoder::SamplingFactor;
+use rgb::FromSlice;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Default AVIF encoder speed, on a scale from 1 (slowest, smallest output) to
+/// 10 (fastest).
+pub const DEFAULT_SPEED: u8 = 6;
+
+/// Accepted values of the `subsampling` parameter.
+pub const SUBSAMPLINGS: [&str; 3] = ["444", "422", "420"];
+
+/// JPEG chroma subsampling. 4:4:4 keeps full color resolution, which avoids
+/// smudged edges in graphics with colored text; 4:2:0 yields smaller photos.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subsampling {
+    Chroma444,
+    Chroma422,
+    Chroma420,
+}
+
+impl FromStr for Subsampling {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Subsampling, Self::Err> {
+        match input {
+            "444" => Ok(Self::Chroma444),
+            "422" => Ok(Self::Chroma422),
+            "420" => Ok(Self::Chroma420),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<Subsampling> for SamplingFactor {
+    fn from(subsampling: Subsampling) -> Self {
+        match subsampling {
+            Subsampling::Chroma444 => SamplingFactor::R_4_4_4,
+            Subsampling::Chroma422 => SamplingFactor::R_4_2_2,
+            Subsampling::Chroma420 => SamplingFactor::R_4_2_0,
+        }
+    }
+}
+
+/// Accepted values of the `compression` parameter.
+pub const PNG_COMPRESSIONS: [&str; 3] = ["fast", "balanced", "best"];
+
+/// PNG compression level and filter strategy, trading encoding time for size.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    // Fast deflate, with the cheapest filter
+    Fast,
+    // Fast deflate, with a filter chosen per scanline
+    #[default]
+    Balanced,
+    // Best deflate, with a filter chosen per scanline
+    Best,
+}
+
+impl FromStr for PngCompression {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<PngCompression, Self::Err> {
+        match input {
+            "fast" => Ok(Self::Fast),
+            "balanced" => Ok(Self::Balanced),
+            "best" => Ok(Self::Best),
+            _ => Err(()),
+        }
+    }
+}
+
+impl PngCompression {
+    fn deflate(&self) -> CompressionType {
+        match self {
+            Self::Fast | Self::Balanced => CompressionType::Fast,
+            Self::Best => CompressionType::Best,
+        }
+    }
+
+    fn filter(&self) -> FilterType {
+        match self {
+            Self::Fast => FilterType::Sub,
+            Self::Balanced | Self::Best => FilterType::Adaptive,
+        }
+    }
+}
+
+/// Encoder settings. Settings that don't apply to the media type being encoded
+/// are ignored.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    pub quality: Option<u8>,
+    // AVIF only
+    pub speed: Option<u8>,
+    // WebP only
+    pub lossless: bool,
+    // JPEG only
+    pub progressive: bool,
+    // JPEG only, defaults to 4:2:0 below quality 90 and 4:4:4 above
+    pub subsampling: Option<Subsampling>,
+    // PNG and GIF only, quantizes the image to a palette of at most this many
+    // colors. GIF output is always quantized, to 256 colors by default.
+    pub colors: Option<u16>,
+    // Whether to apply dithering when quantizing
+    pub dither: bool,
+    // PNG only
+    pub png_compression: PngCompression,
+    // Not supported for AVIF
+    pub metadata: Embedded,
+}
+
+/// Encodes `image` as `media_type`.
+pub fn encode(
+    image: &RgbaImage,
+    media_type: &MediaType,
+    options: &Options,
+) -> ImageResult<Vec<u8>> {
+    let quality = options.quality;
+    let mut bytes: Vec<u8> = Vec::new();
+    match media_type {
+        MediaType::AVIF => {
+            let encoder = ravif::Encoder::new()
+                .with_quality(quality.unwrap_or(80) as f32)
+                .with_speed(options.speed.unwrap_or(DEFAULT_SPEED))
+                .with_alpha_color_mode(ravif::AlphaColorMode::UnassociatedClean);
+            let pixels = ravif::Img::new(
+                image.as_raw().as_rgba(),
+                image.width() as usize,
+                image.height() as usize,
+            );
+            let encoded = encoder
+                .encode_rgba(pixels)
+                .map_err(|e| encoding_error(ImageFormat::Avif, e))?;
+            bytes = encoded.avif_file;
+        }
+        MediaType::JPEG => {
+            let rgb = to_rgb(image);
+            let mut encoder = jpeg_encoder::Encoder::new(&mut bytes, quality.unwrap_or(75));
+            encoder.set_progressive(options.progressive);
+            if let Some(subsampling) = options.subsampling {
+                encoder.set_sampling_factor(subsampling.into());
+            }
+            if let Some(icc_profile) = &options.metadata.icc_profile {
+                encoder
+                    .add_icc_profile(icc_profile)
+                    .map_err(|e| encoding_error(ImageFormat::Jpeg, e))?;
+            }
+            if let Some(exif) = &options.metadata.exif {
+                encoder
+                    .add_app_segment(1, &[b"Exif\0\0", exif.as_slice()].concat())
+                    .map_err(|e| encoding_error(ImageFormat::Jpeg, e))?;
+            }
+            encoder
+                .encode(
+                    rgb.as_raw(),
+                    dimension_u16(rgb.width())?,
+                    dimension_u16(rgb.height())?,
+                    jpeg_encoder::ColorType::Rgb,
+                )
+                .map_err(|e| encoding_error(ImageFormat::Jpeg, e))?;
+        }
+        MediaType::PNG => match options.colors {
+            Some(colors) => {
+                let indexed = quantize::quantize(image, colors, options.dither);
+                write_indexed_png(&mut bytes, &indexed, &options.png_compression)
+                    .map_err(|e| encoding_error(ImageFormat::Png, e))?;
+            }
+            None => {
+                let compression = &options.png_compression;
+                PngEncoder::new_with_quality(
+                    &mut bytes,
+                    compression.deflate(),
+                    compression.filter(),
+                )
+                .write_image(
+                    image.as_raw(),
+                    image.width(),
+                    image.height(),
+                    ColorType::Rgba8,
+                )?;
+            }
+        },
+        MediaType::GIF => {
+            let frame = gif_frame(image, options, 0)?;
+            write_gif(&mut bytes, &[frame], None)
+                .map_err(|e| encoding_error(ImageFormat::Gif, e))?;
+        }
+        MediaType::ICO => bytes = encode_ico(std::slice::from_ref(image))?,
+        MediaType::WEBP => {
+            let config = webp_config(options)?;
+            // Opaque images are encoded without an alpha channel
+            let rgb;
+            let (width, height) = image.dimensions();
+            let encoder = if has_alpha(image) {
+                webp::Encoder::from_rgba(image.as_raw(), width, height)
+            } else {
+                rgb = to_rgb(image);
+                webp::Encoder::from_rgb(rgb.as_raw(), width, height)
+            };
+            let memory = encoder
+                .encode_advanced(&config)
+                .map_err(|e| encoding_error(ImageFormat::WebP, e))?;
+            bytes.extend_from_slice(&memory);
+        }
+    }
+    Ok(metadata::embed(bytes, &options.metadata))
+}
+
+/// Encodes `image` as a PNG with 16 bits per channel. Only the PNG compression
+/// and metadata options apply.
+pub fn encode_png16(image: &Rgba16Image, options: &Options) -> ImageResult<Vec<u8>> {
+    // The encoder expects native endian samples
+    let samples: Vec<u8> = image
+        .as_raw()
+        .iter()
+        .flat_map(|v| v.to_ne_bytes())
+        .collect();
+    let compression = &options.png_compression;
+    let mut bytes = Vec::new();
+    PngEncoder::new_with_quality(&mut bytes, compression.deflate(), compression.filter())
+        .write_image(&samples, image.width(), image.height(), ColorType::Rgba16)?;
+    Ok(metadata::embed(bytes, &options.metadata))
+}
+
+/// Largest width and height of an image in an ICO.
+pub const MAX_ICO_DIMENSION: u32 = 256;
+
+/// Largest width and height of a WebP image.
+pub const MAX_WEBP_DIMENSION: u32 = 16383;
+
+/// Largest width and height `media_type` can encode, or `None` if it has no
+/// limit in practice.
+///
+/// # Examples
+///
+/// ```
+/// use imgconv::encode::max_dimension;
+/// use imgconv::media_type::MediaType;
+/// assert_eq!(max_dimension(&MediaType::WEBP), Some(16383));
+/// assert_eq!(max_dimension(&MediaType::PNG), None);
+/// ```
+pub fn max_dimension(media_type: &MediaType) -> Option<u32> {
+    match media_type {
+        MediaType::ICO => Some(MAX_ICO_DIMENSION),
+        MediaType::WEBP => Some(MAX_WEBP_DIMENSION),
+        // Dimensions are limited to 16 bits
+        MediaType::AVIF | MediaType::GIF | MediaType::JPEG => Some(u16::MAX as u32),
+        MediaType::PNG => None,
+    }
+}
+
+/// Encodes `images` into a single ICO, e.g. a favicon with an image for every
+/// size. Images are stored as PNG, and can't exceed 256x256 pixels.
+pub fn encode_ico(images: &[RgbaImage]) -> ImageResult<Vec<u8>> {
+    let frames = images
+        .iter()
+        .map(|image| {
+            IcoFrame::as_png(
+                image.as_raw(),
+                image.width(),
+                image.height(),
+                ColorType::Rgba8,
+            )
+        })
+        .collect::<ImageResult<Vec<_>>>()?;
+    let mut bytes = Vec::new();
+    IcoEncoder::new(&mut bytes).encode_images(&frames)?;
+    Ok(bytes)
+}
+
+/// Encodes every frame of `animation` into an animated image. Only GIF and
+/// WebP are supported.
+pub fn encode_animation(
+    animation: &Animation,
+    media_type: &MediaType,
+    options: &Options,
+) -> ImageResult<Vec<u8>> {
+    let (width, height) = match animation.frames.first() {
+        Some(frame) => frame.image.dimensions(),
+        None => return Err(encoding_error(ImageFormat::WebP, "no frames")),
+    };
+    match media_type {
+        MediaType::GIF => {
+            let frames = animation
+                .frames
+                .iter()
+                .map(|frame| gif_frame(&frame.image, options, frame.delay))
+                .collect::<ImageResult<Vec<_>>>()?;
+            let mut bytes = Vec::new();
+            write_gif(&mut bytes, &frames, Some(animation.loop_count))
+                .map_err(|e| encoding_error(ImageFormat::Gif, e))?;
+            Ok(bytes)
+        }
+        MediaType::WEBP => {
+            let config = webp_config(options)?;
+            let mut encoder = webp::AnimEncoder::new(width, height, &config);
+            encoder.set_loop_count(animation.loop_count as i32);
+            // Timestamps mark the start of each frame
+            let mut timestamp = 0;
+            for frame in &animation.frames {
+                encoder.add_frame(webp::AnimFrame::from_rgba(
+                    frame.image.as_raw(),
+                    width,
+                    height,
+                    timestamp,
+                ));
+                timestamp += frame.delay as i32;
+            }
+            let memory = encoder
+                .try_encode()
+                .map_err(|e| encoding_error(ImageFormat::WebP, e))?;
+            let mut bytes = memory.to_vec();
+            // The `webp` crate doesn't pass the end of the animation to
+            // libwebp, which then guesses the last frame's delay
+            let last = animation.frames.last().map_or(0, |frame| frame.delay);
+            set_last_frame_duration(&mut bytes, last);
+            Ok(bytes)
+        }
+        _ => Err(ImageError::Unsupported(
+            UnsupportedError::from_format_and_kind(
+                ImageFormatHint::Name(media_type.mime_type().to_owned()),
+                UnsupportedErrorKind::GenericFeature("animation".to_owned()),
+            ),
+        )),
+    }
+}
+
+// Overwrites the duration of the last `ANMF` chunk in an animated WebP
+fn set_last_frame_duration(webp: &mut [u8], duration: u32) {
+    let last = riff::chunks(webp)
+        .filter(|chunk| &chunk.fourcc == b"ANMF")
+        .last()
+        .map(|chunk| chunk.payload.start);
+    // The duration is a 24 bit integer, following the frame's position and
+    // dimensions
+    if let Some(payload) = last.filter(|payload| payload + 15 <= webp.len()) {
+        let duration = duration.min(0xff_ffff).to_le_bytes();
+        webp[payload + 12..payload + 15].copy_from_slice(&duration[..3]);
+    }
+}
+
+fn webp_config(options: &Options) -> ImageResult<webp::WebPConfig> {
+    let mut config = webp::WebPConfig::new().map_err(|e| encoding_error(ImageFormat::WebP, e))?;
+    if options.lossless {
+        config.lossless = 1;
+        // Keep the color of transparent pixels, so the output matches the
+        // source exactly
+        config.exact = 1;
+    } else {
+        config.quality = options.quality.unwrap_or(75) as f32;
+    }
+    Ok(config)
+}
+
+/// Lowest quality `encode_within` will try before downscaling the image.
+pub const MIN_QUALITY: u8 = 10;
+
+// Factor by which `encode_within` downscales the image at every step
+const DOWNSCALE_FACTOR: f64 = 0.75;
+
+// `encode_within` gives up once the image gets smaller than this
+const MIN_DIMENSION: u32 = 16;
+
+/// An image encoded within a byte budget.
+#[derive(Debug)]
+pub struct Fitted {
+    pub bytes: Vec<u8>,
+    pub quality: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Encodes `image` at the highest quality (up to `options.quality`) that
+/// yields at most `max_bytes`, using a binary search over re-encodes. If the
+/// output doesn't fit even at `MIN_QUALITY`, the image is downscaled step by
+/// step, in linear light if `linear` is set. Returns `None` if the image
+/// doesn't fit at any size.
+///
+/// Only meaningful for lossy media types.
+pub fn encode_within(
+    image: &RgbaImage,
+    media_type: &MediaType,
+    options: &Options,
+    max_bytes: usize,
+    linear: bool,
+) -> ImageResult<Option<Fitted>> {
+    let upper = options.quality.unwrap_or(100);
+    let lower = MIN_QUALITY.min(upper);
+    let mut scale = 1.;
+    let mut scaled = image.clone();
+    loop {
+        let (mut lo, mut hi) = (lower, upper);
+        let mut best = None;
+        while lo <= hi {
+            let quality = lo + (hi - lo) / 2;
+            let options = Options {
+                quality: Some(quality),
+                ..options.clone()
+            };
+            let bytes = encode(&scaled, media_type, &options)?;
+            if bytes.len() <= max_bytes {
+                best = Some((quality, bytes));
+                lo = quality + 1;
+            } else if quality == lower {
+                break;
+            } else {
+                hi = quality - 1;
+            }
+        }
+        if let Some((quality, bytes)) = best {
+            return Ok(Some(Fitted {
+                bytes,
+                quality,
+                width: scaled.width(),
+                height: scaled.height(),
+            }));
+        }
+
+        scale *= DOWNSCALE_FACTOR;
+        let w = (image.width() as f64 * scale) as u32;
+        let h = (image.height() as f64 * scale) as u32;
+        if w < MIN_DIMENSION || h < MIN_DIMENSION {
+            return Ok(None);
+        }
+        scaled = resize::resize(&DynamicImage::ImageRgba8(image.clone()), w, h, linear);
+    }
+}
+
+/// Finds the lowest quality at which the encoded image still has an SSIM of
+/// at least `target` relative to `image`, using a binary search over
+/// re-encodes.
+///
+/// Only supports media types that can be decoded again, i.e. JPEG and WebP.
+pub fn encode_perceptual(
+    image: &RgbaImage,
+    media_type: &MediaType,
+    options: &Options,
+    target: f64,
+) -> ImageResult<Fitted> {
+    let format = match media_type {
+        MediaType::JPEG => ImageFormat::Jpeg,
+        MediaType::WEBP => ImageFormat::WebP,
+        _ => {
+            return Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    ImageFormatHint::Name(media_type.mime_type().to_owned()),
+                    UnsupportedErrorKind::GenericFeature("perceptual quality".to_owned()),
+                ),
+            ))
+        }
+    };
+    let (mut lo, mut hi) = (MIN_QUALITY, 100);
+    let mut best = None;
+    while lo <= hi {
+        let quality = lo + (hi - lo) / 2;
+        let options = Options {
+            quality: Some(quality),
+            ..options.clone()
+        };
+        let bytes = encode(image, media_type, &options)?;
+        let decoded = image::load_from_memory_with_format(&bytes, format)?.into_rgba8();
+        if ssim::ssim(image, &decoded) >= target {
+            best = Some((quality, bytes));
+            if quality == MIN_QUALITY {
+                break;
+            }
+            hi = quality - 1;
+        } else {
+            lo = quality + 1;
+        }
+    }
+    let (quality, bytes) = match best {
+        Some(best) => best,
+        // Not even the highest quality reaches the target
+        None => (
+            100,
+            encode(
+                image,
+                media_type,
+                &Options {
+                    quality: Some(100),
+                    ..options.clone()
+                },
+            )?,
+        ),
+    };
+    Ok(Fitted {
+        bytes,
+        quality,
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+/// Whether any pixel in the image is (partially) transparent.
+pub fn has_alpha(image: &RgbaImage) -> bool {
+    image.pixels().any(|p| p[3] < u8::MAX)
+}
+
+fn write_indexed_png(
+    bytes: &mut Vec<u8>,
+    indexed: &Indexed,
+    compression: &PngCompression,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(bytes, indexed.width, indexed.height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_compression(match compression {
+        PngCompression::Fast | PngCompression::Balanced => png::Compression::Fast,
+        PngCompression::Best => png::Compression::Best,
+    });
+    // Filtering rarely pays off for palette images
+    encoder.set_filter(png::FilterType::NoFilter);
+    encoder.set_depth(png::BitDepth::from_u8(indexed.bit_depth()).unwrap());
+    encoder.set_palette(indexed.rgb_palette());
+    let transparency = indexed.transparency();
+    if !transparency.is_empty() {
+        encoder.set_trns(transparency);
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&indexed.packed_pixels())
+}
+
+// Quantizes `image` into a GIF frame with its own palette. GIF only supports
+// fully transparent pixels, so other pixels are made opaque.
+fn gif_frame(image: &RgbaImage, options: &Options, delay: u32) -> ImageResult<gif::Frame<'static>> {
+    let mut binary_alpha = image.clone();
+    for pixel in binary_alpha.pixels_mut() {
+        pixel.0 = if pixel[3] < 128 {
+            [0, 0, 0, 0]
+        } else {
+            [pixel[0], pixel[1], pixel[2], u8::MAX]
+        };
+    }
+    let indexed = quantize::quantize(
+        &binary_alpha,
+        options.colors.unwrap_or(quantize::MAX_COLORS),
+        options.dither,
+    );
+    Ok(gif::Frame {
+        width: dimension_u16(indexed.width)?,
+        height: dimension_u16(indexed.height)?,
+        palette: Some(indexed.rgb_palette()),
+        // Transparent entries come first
+        transparent: indexed.palette.first().filter(|c| c[3] == 0).map(|_| 0),
+        // GIF delays are in units of 10 ms
+        delay: ((delay + 5) / 10).min(u16::MAX as u32) as u16,
+        // Frames cover the whole canvas, so earlier frames mustn't show
+        // through transparent pixels
+        dispose: gif::DisposalMethod::Background,
+        buffer: indexed.pixels.into(),
+        ..Default::default()
+    })
+}
+
+// Writes a GIF, looping `loop_count` times (0 meaning forever) if it's
+// animated
+fn write_gif(
+    bytes: &mut Vec<u8>,
+    frames: &[gif::Frame],
+    loop_count: Option<u32>,
+) -> Result<(), gif::EncodingError> {
+    let (width, height) = frames.first().map_or((0, 0), |f| (f.width, f.height));
+    let mut encoder = gif::Encoder::new(bytes, width, height, &[])?;
+    match loop_count {
+        Some(0) => encoder.set_repeat(gif::Repeat::Infinite)?,
+        Some(n) => encoder.set_repeat(gif::Repeat::Finite(n.min(u16::MAX as u32) as u16))?,
+        None => {}
+    }
+    for frame in frames {
+        encoder.write_frame(frame)?;
+    }
+    Ok(())
+}
+
+fn dimension_u16(length: u32) -> ImageResult<u16> {
+    u16::try_from(length)
+        .map_err(|_| ImageError::Limits(LimitError::from_kind(LimitErrorKind::DimensionError)))
+}
+
+fn to_rgb(image: &RgbaImage) -> RgbImage {
+    DynamicImage::ImageRgba8(image.clone()).into_rgb8()
+}
+
+fn encoding_error(format: ImageFormat, err: impl std::fmt::Debug) -> ImageError {
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(format),
+        format!("{:?}", err),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_has_alpha() {
+        let mut image = RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+        assert!(!has_alpha(&image));
+        image.put_pixel(1, 1, Rgba([255, 0, 0, 254]));
+        assert!(has_alpha(&image));
+    }
+
+    #[test]
+    fn test_encode_yields_requested_format() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([0, 128, 255, 255]));
+        for (media_type, format) in [
+            (MediaType::JPEG, ImageFormat::Jpeg),
+            (MediaType::PNG, ImageFormat::Png),
+            (MediaType::WEBP, ImageFormat::WebP),
+        ] {
+            let options = Options {
+                quality: Some(80),
+                ..Default::default()
+            };
+            let bytes = encode(&image, &media_type, &options).unwrap();
+            assert_eq!(image::guess_format(&bytes).unwrap(), format);
+        }
+    }
+
+    #[test]
+    fn test_encode_avif() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([0, 128, 255, 128]));
+        let bytes = encode(&image, &MediaType::AVIF, &Options::default()).unwrap();
+        assert_eq!(&bytes[4..12], b"ftypavif");
+    }
+
+    #[test]
+    fn test_encode_lossless_webp_preserves_alpha() {
+        let mut image = RgbaImage::from_pixel(4, 4, Rgba([0, 128, 255, 255]));
+        image.put_pixel(0, 0, Rgba([10, 20, 30, 0]));
+        image.put_pixel(1, 0, Rgba([40, 50, 60, 128]));
+        let options = Options {
+            lossless: true,
+            ..Default::default()
+        };
+        let bytes = encode(&image, &MediaType::WEBP, &options).unwrap();
+        let decoded = webp::Decoder::new(&bytes).decode().unwrap();
+        assert!(decoded.is_alpha());
+        assert_eq!(&*decoded, image.as_raw().as_slice());
+    }
+
+    #[test]
+    fn test_encode_opaque_webp_has_no_alpha() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([0, 128, 255, 255]));
+        let bytes = encode(&image, &MediaType::WEBP, &Options::default()).unwrap();
+        assert!(!webp::Decoder::new(&bytes).decode().unwrap().is_alpha());
+    }
+
+    #[test]
+    fn test_subsampling_from_str() {
+        assert_eq!(Subsampling::from_str("444"), Ok(Subsampling::Chroma444));
+        assert!(Subsampling::from_str("4:4:4").is_err());
+    }
+
+    #[test]
+    fn test_encode_progressive_jpeg() {
+        let image = RgbaImage::from_pixel(16, 16, Rgba([255, 0, 0, 255]));
+        let options = Options {
+            progressive: true,
+            subsampling: Some(Subsampling::Chroma444),
+            ..Default::default()
+        };
+        let bytes = encode(&image, &MediaType::JPEG, &options).unwrap();
+        // SOF2 marks a progressive DCT frame
+        assert!(bytes.windows(2).any(|marker| marker == [0xFF, 0xC2]));
+        assert!(image::load_from_memory(&bytes).is_ok());
+    }
+
+    #[test]
+    fn test_encode_indexed_png() {
+        let mut image = RgbaImage::from_pixel(8, 8, Rgba([255, 0, 0, 255]));
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        let options = Options {
+            colors: Some(4),
+            ..Default::default()
+        };
+        let bytes = encode(&image, &MediaType::PNG, &options).unwrap();
+        let decoder = png::Decoder::new(bytes.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!(info.color_type, png::ColorType::Indexed);
+        assert!(info.trns.is_some());
+        let decoded = image::load_from_memory(&bytes).unwrap().into_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+        assert_eq!(decoded.get_pixel(7, 7), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_png_compression_from_str() {
+        assert_eq!(PngCompression::from_str("best"), Ok(PngCompression::Best));
+        assert!(PngCompression::from_str("default").is_err());
+    }
+
+    #[test]
+    fn test_encode_best_png_is_not_larger() {
+        let image = RgbaImage::from_fn(64, 64, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let size = |png_compression| {
+            let options = Options {
+                png_compression,
+                ..Default::default()
+            };
+            encode(&image, &MediaType::PNG, &options).unwrap().len()
+        };
+        assert!(size(PngCompression::Best) <= size(PngCompression::Fast));
+    }
+
+    fn noise(size: u32) -> RgbaImage {
+        RgbaImage::from_fn(size, size, |x, y| {
+            let v = (x * 7919 + y * 104729) ^ (x * y);
+            Rgba([v as u8, (v >> 3) as u8, (v >> 5) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn test_encode_within_keeps_upper_quality_if_it_fits() {
+        let options = Options {
+            quality: Some(70),
+            ..Default::default()
+        };
+        let fitted = encode_within(&noise(32), &MediaType::JPEG, &options, usize::MAX, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(fitted.quality, 70);
+        assert_eq!((fitted.width, fitted.height), (32, 32));
+    }
+
+    #[test]
+    fn test_encode_within_lowers_quality() {
+        let image = noise(64);
+        let options = Options {
+            quality: Some(90),
+            ..Default::default()
+        };
+        let full = encode(&image, &MediaType::JPEG, &options).unwrap().len();
+        let fitted = encode_within(&image, &MediaType::JPEG, &options, full - 1, false)
+            .unwrap()
+            .unwrap();
+        assert!(fitted.quality < 90);
+        assert!(fitted.bytes.len() < full);
+        assert_eq!(fitted.width, 64);
+    }
+
+    #[test]
+    fn test_encode_within_downscales() {
+        let image = noise(256);
+        let options = Options {
+            quality: Some(MIN_QUALITY),
+            ..Default::default()
+        };
+        let smallest = encode(&image, &MediaType::JPEG, &options).unwrap().len();
+        let fitted = encode_within(&image, &MediaType::JPEG, &options, smallest / 2, false)
+            .unwrap()
+            .unwrap();
+        assert!(fitted.width < 256);
+        assert!(fitted.bytes.len() <= smallest / 2);
+    }
+
+    #[test]
+    fn test_encode_within_downscales_without_fringes() {
+        // Transparent black on the left, opaque white on the right
+        let image = RgbaImage::from_fn(256, 256, |x, _| {
+            if x < 128 {
+                Rgba([0, 0, 0, 0])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let full = encode(&image, &MediaType::PNG, &Options::default())
+            .unwrap()
+            .len();
+        let fitted = encode_within(
+            &image,
+            &MediaType::PNG,
+            &Options::default(),
+            full - 1,
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(fitted.width < 256);
+        let decoded = image::load_from_memory(&fitted.bytes).unwrap().to_rgba8();
+        for pixel in decoded.pixels().filter(|pixel| pixel[3] > 0) {
+            assert!(pixel[0] >= 250, "dark fringe: {:?}", pixel);
+        }
+    }
+
+    #[test]
+    fn test_encode_within_gives_up() {
+        let fitted =
+            encode_within(&noise(64), &MediaType::JPEG, &Options::default(), 10, false).unwrap();
+        assert!(fitted.is_none());
+    }
+
+    #[test]
+    fn test_encode_perceptual_meets_target() {
+        let image = noise(64);
+        for media_type in [MediaType::JPEG, MediaType::WEBP] {
+            let fitted = encode_perceptual(&image, &media_type, &Options::default(), 0.95).unwrap();
+            let decoded = image::load_from_memory(&fitted.bytes).unwrap().into_rgba8();
+            assert!(ssim::ssim(&image, &decoded) >= 0.95);
+        }
+    }
+
+    #[test]
+    fn test_encode_perceptual_lower_target_lowers_quality() {
+        let image = noise(64);
+        let quality = |target| {
+            encode_perceptual(&image, &MediaType::JPEG, &Options::default(), target)
+                .unwrap()
+                .quality
+        };
+        assert!(quality(0.5) < quality(0.99));
+    }
+
+    #[test]
+    fn test_encode_perceptual_unsupported_media_type() {
+        let image = noise(16);
+        assert!(encode_perceptual(&image, &MediaType::AVIF, &Options::default(), 0.9).is_err());
+    }
+
+    #[test]
+    fn test_encode_embeds_icc_profile() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([0, 128, 255, 255]));
+        let options = Options {
+            metadata: Embedded {
+                icc_profile: Some(b"profile".to_vec()),
+                exif: None,
+            },
+            ..Default::default()
+        };
+        for media_type in [MediaType::JPEG, MediaType::PNG, MediaType::WEBP] {
+            let bytes = encode(&image, &media_type, &options).unwrap();
+            let read = metadata::read(&bytes, metadata::Metadata::Strip);
+            assert_eq!(read.icc_profile, Some(b"profile".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_encode_animation_webp() {
+        let animation = Animation {
+            frames: [(0, 100), (255, 250)]
+                .into_iter()
+                .map(|(v, delay)| crate::animation::Frame {
+                    image: RgbaImage::from_pixel(8, 8, Rgba([v, v, v, 255])),
+                    delay,
+                })
+                .collect(),
+            loop_count: 5,
+        };
+        let bytes = encode_animation(&animation, &MediaType::WEBP, &Options::default()).unwrap();
+        let decoded = crate::animation::decode(&bytes).unwrap();
+        assert_eq!(decoded.loop_count, 5);
+        let delays: Vec<u32> = decoded.frames.iter().map(|f| f.delay).collect();
+        assert_eq!(delays, vec![100, 250]);
+    }
+
+    #[test]
+    fn test_encode_gif() {
+        let mut image = noise(32);
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 0]));
+        let options = Options {
+            colors: Some(16),
+            ..Default::default()
+        };
+        let bytes = encode(&image, &MediaType::GIF, &options).unwrap();
+        let decoded = image::load_from_memory(&bytes).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (32, 32));
+        assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+        assert!(decoded.pixels().skip(1).all(|p| p[3] == 255));
+    }
+
+    #[test]
+    fn test_encode_animation_gif() {
+        let animation = Animation {
+            frames: [(0, 100), (255, 250)]
+                .into_iter()
+                .map(|(v, delay)| crate::animation::Frame {
+                    image: RgbaImage::from_pixel(8, 8, Rgba([v, v, v, 255])),
+                    delay,
+                })
+                .collect(),
+            loop_count: 0,
+        };
+        let bytes = encode_animation(&animation, &MediaType::GIF, &Options::default()).unwrap();
+        let decoded = crate::animation::decode(&bytes).unwrap();
+        assert_eq!(decoded.loop_count, 0);
+        let delays: Vec<u32> = decoded.frames.iter().map(|f| f.delay).collect();
+        assert_eq!(delays, vec![100, 250]);
+        assert_eq!(
+            decoded.frames[1].image.get_pixel(0, 0).0,
+            [255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_encode_png16() {
+        let image = Rgba16Image::from_pixel(2, 2, Rgba([1000, 2000, 65535, 30000]));
+        let bytes = encode_png16(&image, &Options::default()).unwrap();
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(decoded.color(), ColorType::Rgba16);
+        assert_eq!(
+            decoded.to_rgba16().get_pixel(1, 1).0,
+            [1000, 2000, 65535, 30000]
+        );
+    }
+
+    #[test]
+    fn test_encode_ico() {
+        let images: Vec<RgbaImage> = [16, 32, 256].into_iter().map(noise).collect();
+        let bytes = encode_ico(&images).unwrap();
+        assert_eq!(&bytes[..4], &[0, 0, 1, 0]);
+        // The number of images
+        assert_eq!(u16::from_le_bytes([bytes[4], bytes[5]]), 3);
+        // The decoder picks the largest image
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (256, 256));
+    }
+
+    #[test]
+    fn test_encode_ico_too_large() {
+        assert!(encode(&noise(257), &MediaType::ICO, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn test_max_dimension_matches_encoders() {
+        for media_type in [MediaType::GIF, MediaType::JPEG, MediaType::WEBP] {
+            let max = max_dimension(&media_type).unwrap();
+            let fits = RgbaImage::new(max, 1);
+            assert!(encode(&fits, &media_type, &Options::default()).is_ok());
+            let too_wide = RgbaImage::new(max + 1, 1);
+            assert!(encode(&too_wide, &media_type, &Options::default()).is_err());
+        }
+    }
+}
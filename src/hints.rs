@@ -0,0 +1,295 @@
+//! Client Hints allow a browser to tell us the width an image is rendered at
+//! and the device pixel ratio (DPR) of the screen, so that the `w` and `dpr`
+//! query parameters may be omitted.
+//!
+//! Widths derived from client hints are snapped to a list of breakpoints, so
+//! that the slightly different widths reported by different devices don't
+//! each end up as a separate cache entry.
+
+use actix_web::http::header::HeaderMap;
+
+pub const WIDTH: &str = "Sec-CH-Width";
+pub const DPR: &str = "Sec-CH-DPR";
+pub const VIEWPORT_WIDTH: &str = "Sec-CH-Viewport-Width";
+
+/// Value of the `Accept-CH` response header.
+pub const ACCEPT_CH: &str = "Sec-CH-Width, Sec-CH-DPR, Sec-CH-Viewport-Width";
+
+pub const MIN_DPR: f64 = 1.;
+pub const MAX_DPR: f64 = 4.;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ClientHints {
+    // Rendered width of the image in device pixels
+    pub width: Option<u32>,
+    pub dpr: Option<f64>,
+    // Width of the viewport in CSS pixels
+    pub viewport_width: Option<u32>,
+}
+
+impl ClientHints {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+        Self {
+            width: get(WIDTH).and_then(|v| v.parse().ok()).filter(|w| *w > 0),
+            dpr: get(DPR)
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|d| d.is_finite() && *d > 0.),
+            viewport_width: get(VIEWPORT_WIDTH)
+                .and_then(|v| v.parse().ok())
+                .filter(|w| *w > 0),
+        }
+    }
+}
+
+/// Requested dimensions in device pixels, along with the client hints they
+/// depend on (for the `Vary` response header).
+#[derive(Debug, PartialEq)]
+pub struct Dimensions {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    pub vary: Vec<&'static str>,
+}
+
+/// Combines the `w`, `h` and `dpr` query parameters with the client hints sent
+/// by the browser. Query parameters always take precedence over hints. The
+/// viewport width is only used if neither `w` nor `h` is given, so that
+/// requests for a height aren't narrowed down to the viewport.
+///
+/// # Examples
+///
+/// ```
+/// use imgconv::hints::{resolve, ClientHints};
+/// let hints = ClientHints {
+///     width: None,
+///     dpr: Some(2.),
+///     viewport_width: Some(390),
+/// };
+/// let dimensions = resolve(None, None, None, &hints, &[640, 800, 1024]);
+/// assert_eq!(dimensions.w, Some(800));
+/// ```
+pub fn resolve(
+    w: Option<u32>,
+    h: Option<u32>,
+    dpr: Option<f64>,
+    hints: &ClientHints,
+    breakpoints: &[u32],
+) -> Dimensions {
+    let mut vary = Vec::new();
+    if dpr.is_none() {
+        vary.push(DPR);
+    }
+    let dpr_hinted = dpr.is_none() && hints.dpr.is_some();
+    let dpr = dpr.or(hints.dpr).unwrap_or(1.).clamp(MIN_DPR, MAX_DPR);
+    // A width is snapped as soon as any hint contributed to it
+    let (w, hinted) = match w {
+        Some(w) => (Some(scale(w, dpr)), dpr_hinted),
+        None => {
+            vary.push(WIDTH);
+            let viewport_width = hints.viewport_width.filter(|_| h.is_none());
+            if h.is_none() {
+                vary.push(VIEWPORT_WIDTH);
+            }
+            match (hints.width, viewport_width) {
+                (Some(width), _) => (Some(width), true),
+                (None, Some(viewport_width)) => (Some(scale(viewport_width, dpr)), true),
+                (None, None) => (None, false),
+            }
+        }
+    };
+    let h = h.map(|h| scale(h, dpr));
+    match w {
+        Some(w) if hinted => {
+            let snapped = snap(w, breakpoints);
+            Dimensions {
+                w: Some(snapped),
+                // Keep the aspect ratio of the requested box intact
+                h: h.map(|h| (h as f64 * snapped as f64 / w as f64).round() as u32),
+                vary,
+            }
+        }
+        _ => Dimensions { w, h, vary },
+    }
+}
+
+fn scale(length: u32, dpr: f64) -> u32 {
+    (length as f64 * dpr).round() as u32
+}
+
+// Returns the smallest breakpoint that is at least as wide as `width`, or the
+// largest breakpoint if `width` exceeds all of them.
+fn snap(width: u32, breakpoints: &[u32]) -> u32 {
+    breakpoints
+        .iter()
+        .filter(|b| **b >= width)
+        .min()
+        .or_else(|| breakpoints.iter().max())
+        .copied()
+        .unwrap_or(width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    const BREAKPOINTS: [u32; 4] = [320, 640, 1024, 1920];
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_from_headers() {
+        assert_eq!(
+            ClientHints::from_headers(&headers(&[
+                ("sec-ch-width", "800"),
+                ("sec-ch-dpr", "2.625"),
+                ("sec-ch-viewport-width", " 412 "),
+            ])),
+            ClientHints {
+                width: Some(800),
+                dpr: Some(2.625),
+                viewport_width: Some(412),
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_headers_ignores_invalid_values() {
+        assert_eq!(
+            ClientHints::from_headers(&headers(&[
+                ("sec-ch-width", "-1"),
+                ("sec-ch-dpr", "NaN"),
+                ("sec-ch-viewport-width", "0"),
+            ])),
+            ClientHints::default()
+        );
+    }
+
+    #[test]
+    fn test_snap() {
+        assert_eq!(snap(1, &BREAKPOINTS), 320);
+        assert_eq!(snap(640, &BREAKPOINTS), 640);
+        assert_eq!(snap(641, &BREAKPOINTS), 1024);
+    }
+
+    #[test]
+    fn test_snap_beyond_largest_breakpoint() {
+        assert_eq!(snap(4000, &BREAKPOINTS), 1920);
+    }
+
+    #[test]
+    fn test_snap_without_breakpoints() {
+        assert_eq!(snap(641, &[]), 641);
+    }
+
+    #[test]
+    fn test_resolve_query_takes_precedence() {
+        let hints = ClientHints {
+            width: Some(1000),
+            dpr: Some(3.),
+            viewport_width: Some(400),
+        };
+        assert_eq!(
+            resolve(Some(300), Some(200), Some(2.), &hints, &BREAKPOINTS),
+            Dimensions {
+                w: Some(600),
+                h: Some(400),
+                vary: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_width_hint() {
+        let hints = ClientHints {
+            width: Some(1000),
+            dpr: Some(2.),
+            viewport_width: Some(400),
+        };
+        assert_eq!(
+            resolve(None, Some(250), None, &hints, &BREAKPOINTS),
+            Dimensions {
+                w: Some(1024),
+                h: Some(512),
+                vary: vec![DPR, WIDTH],
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_viewport_width_hint() {
+        let hints = ClientHints {
+            width: None,
+            dpr: Some(1.5),
+            viewport_width: Some(400),
+        };
+        assert_eq!(
+            resolve(None, None, None, &hints, &BREAKPOINTS),
+            Dimensions {
+                w: Some(640),
+                h: None,
+                vary: vec![DPR, WIDTH, VIEWPORT_WIDTH],
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_height_ignores_viewport_width_hint() {
+        let hints = ClientHints {
+            width: None,
+            dpr: Some(2.),
+            viewport_width: Some(400),
+        };
+        assert_eq!(
+            resolve(None, Some(250), None, &hints, &BREAKPOINTS),
+            Dimensions {
+                w: None,
+                h: Some(500),
+                vary: vec![DPR, WIDTH],
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_dpr_hint_snaps_width() {
+        let hints = ClientHints {
+            width: None,
+            dpr: Some(2.625),
+            viewport_width: None,
+        };
+        assert_eq!(
+            resolve(Some(300), None, None, &hints, &BREAKPOINTS),
+            Dimensions {
+                w: Some(1024),
+                h: None,
+                vary: vec![DPR],
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_without_hints() {
+        assert_eq!(
+            resolve(Some(300), None, None, &ClientHints::default(), &BREAKPOINTS),
+            Dimensions {
+                w: Some(300),
+                h: None,
+                vary: vec![DPR],
+            }
+        );
+    }
+}
@@ -2,6 +2,22 @@
 //!
 //! `imgconv` is an image transcoding web service.
 
-mod calc;
+pub mod animation;
+pub mod calc;
+pub mod color;
+pub mod encode;
+pub mod hints;
+pub mod media_type;
+pub mod metadata;
+pub mod organization;
+pub mod pages;
+pub mod palette;
+pub mod placeholder;
+pub mod quantize;
+pub mod resize;
+pub mod riff;
+pub mod source;
+pub mod ssim;
+pub mod svg;
 
 pub use calc::true_focal_point;
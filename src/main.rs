@@ -1,49 +1,32 @@
-mod calc;
+use imgconv::animation::{self, FrameSelector};
+use imgconv::calc;
+use imgconv::color::{self, Color};
+use imgconv::encode;
+use imgconv::hints::{self, ClientHints};
+use imgconv::media_type::{self, MediaType, DEFAULT_QUALITY, MEDIA_TYPES};
+use imgconv::metadata::{self, Embedded, Metadata};
+use imgconv::organization::{LoadError, Organization};
+use imgconv::pages;
+use imgconv::palette::{self, Swatch};
+use imgconv::placeholder;
+use imgconv::resize::{self, Rgba16Image};
+use imgconv::source::{self, InputFormat};
+use imgconv::svg::{self, Svg};
 
-use actix_web::{get, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use actix_web_validator::Query;
-use image::io::Reader as ImageReader;
-use image::{imageops, GenericImageView};
-use serde::Deserialize;
-use std::io::Cursor;
+use image::{imageops, DynamicImage, GenericImageView, ImageBuffer, ImageError, Pixel, RgbaImage};
+use serde::{Deserialize, Serialize};
 use std::str;
 use std::str::FromStr;
 use validator::{Validate, ValidationError};
 
-const MEDIA_TYPES: [&str; 3] = ["jpeg", "png", "webp"];
-
-#[derive(Debug, PartialEq)]
-pub enum MediaType {
-    JPEG,
-    WEBP,
-    PNG,
-}
-
-impl MediaType {
-    const DEFAULT: Self = Self::WEBP;
-}
-
-impl FromStr for MediaType {
-    type Err = ();
-
-    fn from_str(input: &str) -> Result<MediaType, Self::Err> {
-        match input {
-            "jpeg" => Ok(Self::JPEG),
-            "png" => Ok(Self::PNG),
-            "webp" => Ok(Self::WEBP),
-            _ => Err(()),
-        }
-    }
-}
-
-pub const DEFAULT_MEDIA_TYPE: MediaType = MediaType::JPEG;
-
-pub const DEFAULT_QUALITY: [(MediaType, u8); 2] = [(MediaType::JPEG, 70), (MediaType::WEBP, 60)];
-
 #[derive(Deserialize, Debug)]
 struct PathInfo {
+    #[allow(dead_code)]
     signature: String,
     organization_id: String,
+    #[allow(dead_code)]
     media_id: String,
 }
 
@@ -54,19 +37,41 @@ struct QueryInfo {
     resize: Option<String>,
     w: Option<u32>,
     h: Option<u32>,
+    #[validate(range(min = 1., max = 4.))]
+    dpr: Option<f64>,
     #[validate(range(min = 0.5, max = 2.))]
     zoom: Option<f64>,
+    linear: Option<bool>,
     #[validate(custom = "validate_media_type")]
     media_type: Option<String>,
-    #[validate(range(min = 0, max = 100))]
-    quality: Option<u8>,
+    #[validate(custom = "validate_quality")]
+    quality: Option<String>,
+    #[validate(range(min = 1, max = 10))]
+    speed: Option<u8>,
+    lossless: Option<bool>,
+    progressive: Option<bool>,
+    #[validate(custom = "validate_subsampling")]
+    subsampling: Option<String>,
+    #[validate(range(min = 2, max = 256))]
+    colors: Option<u16>,
+    dither: Option<bool>,
+    #[validate(custom = "validate_compression")]
+    compression: Option<String>,
+    #[validate(range(min = 1))]
+    max_bytes: Option<u32>,
+    #[validate(custom = "validate_metadata")]
+    metadata: Option<String>,
     #[validate(range(min = 0., max = 100.))]
     fx: Option<f64>,
     #[validate(range(min = 0., max = 100.))]
     fy: Option<f64>,
+    #[validate(custom = "validate_bgcolor")]
+    bgcolor: Option<String>,
+    #[validate(custom = "validate_frame")]
+    frame: Option<String>,
+    page: Option<u32>,
     // blur: Option<f64>,
     // grayscale: Option<bool>,
-    // bgcolor: Option<String>,
     // debug: Option<bool>,
 }
 
@@ -74,6 +79,7 @@ impl QueryInfo {
     const DEFAULT_RESIZE: &str = "fit";
     const DEFAULT_FX: f64 = 50.;
     const DEFAULT_FY: f64 = 50.;
+    const AUTO_QUALITY: &str = "auto";
 
     pub fn get_default_quality_for_media_type(media_type: &MediaType) -> Result<u8, &'static str> {
         for (media_type_2, default_quality) in DEFAULT_QUALITY.into_iter() {
@@ -86,31 +92,99 @@ impl QueryInfo {
 }
 
 fn validate_query_info(query_info: &QueryInfo) -> Result<(), ValidationError> {
-    if query_info.w == None && query_info.h == None {
-        return Err(ValidationError::new(
-            "At least one of `w`, `h` must be provided",
-        ));
-    }
-    if query_info.resize == Some("crop".to_owned())
-        && (query_info.h == None || query_info.w == None)
-    {
+    // `w` may be omitted if the browser sends client hints, which is checked
+    // once the hints have been resolved.
+    if query_info.resize == Some("crop".to_owned()) && query_info.h.is_none() {
         return Err(ValidationError::new(
             "For resize `crop` both `w` and `h` must be provided",
         ));
     }
-    let media_type = match &query_info.media_type {
-        Some(m) => MediaType::from_str(m).unwrap(),
-        None => MediaType::DEFAULT,
+    // With `auto` the media type is only known after negotiation, and
+    // `quality` is ignored if the negotiated media type doesn't support it.
+    let media_type = match query_info.media_type.as_deref() {
+        Some(media_type::AUTO) => None,
+        Some(m) => MediaType::from_str(m).ok(),
+        None => Some(MediaType::DEFAULT),
     };
-    if QueryInfo::get_default_quality_for_media_type(&media_type).is_err()
+    if media_type
+        .as_ref()
+        .is_some_and(|m| QueryInfo::get_default_quality_for_media_type(m).is_err())
         && query_info.quality.is_some()
     {
         return Err(ValidationError::new("Media type does not support quality"));
     }
+    if media_type.as_ref().is_some_and(|m| m != &MediaType::AVIF) && query_info.speed.is_some() {
+        return Err(ValidationError::new("Media type does not support speed"));
+    }
+    if query_info.lossless == Some(true) {
+        if media_type != Some(MediaType::WEBP) {
+            return Err(ValidationError::new("Media type does not support lossless"));
+        }
+        if query_info.quality.is_some() {
+            return Err(ValidationError::new(
+                "`quality` cannot be combined with lossless output",
+            ));
+        }
+    }
+    if (query_info.progressive.is_some() || query_info.subsampling.is_some())
+        && media_type != Some(MediaType::JPEG)
+    {
+        return Err(ValidationError::new(
+            "`progressive` and `subsampling` require media type `jpeg`",
+        ));
+    }
+    if query_info.colors.is_some() && !matches!(media_type, Some(MediaType::PNG | MediaType::GIF)) {
+        return Err(ValidationError::new("Media type does not support colors"));
+    }
+    // GIF output is always quantized
+    if query_info.dither.is_some()
+        && query_info.colors.is_none()
+        && media_type != Some(MediaType::GIF)
+    {
+        return Err(ValidationError::new(
+            "`dither` requires `colors` or media type `gif`",
+        ));
+    }
+    if query_info.compression.is_some() && media_type != Some(MediaType::PNG) {
+        return Err(ValidationError::new(
+            "Media type does not support compression",
+        ));
+    }
+    if query_info.max_bytes.is_some()
+        && (!matches!(
+            media_type,
+            Some(MediaType::AVIF | MediaType::JPEG | MediaType::WEBP)
+        ) || query_info.lossless == Some(true))
+    {
+        return Err(ValidationError::new(
+            "`max_bytes` requires a lossy media type",
+        ));
+    }
+    if query_info.bgcolor.is_some() && media_type.as_ref().is_some_and(|m| m != &MediaType::JPEG) {
+        return Err(ValidationError::new("Media type does not support bgcolor"));
+    }
+    if query_info.quality.as_deref() == Some(QueryInfo::AUTO_QUALITY)
+        && media_type
+            .as_ref()
+            .is_some_and(|m| !matches!(m, MediaType::JPEG | MediaType::WEBP))
+    {
+        return Err(ValidationError::new(
+            "Media type does not support quality `auto`",
+        ));
+    }
 
     Ok(())
 }
 
+fn validate_quality(quality: &str) -> Result<(), ValidationError> {
+    if quality != QueryInfo::AUTO_QUALITY && !quality.parse::<u8>().is_ok_and(|q| q <= 100) {
+        return Err(ValidationError::new(
+            "Quality must be `auto` or between 0 and 100",
+        ));
+    }
+    Ok(())
+}
+
 fn validate_resize(resize: &str) -> Result<(), ValidationError> {
     if !["fit", "crop"].contains(&resize) {
         return Err(ValidationError::new(
@@ -121,37 +195,335 @@ fn validate_resize(resize: &str) -> Result<(), ValidationError> {
 }
 
 fn validate_media_type(media_type: &str) -> Result<(), ValidationError> {
-    if !MEDIA_TYPES.contains(&media_type) {
+    if media_type != media_type::AUTO && !MEDIA_TYPES.contains(&media_type) {
+        return Err(ValidationError::new(
+            "Media type must be `auto`, `avif`, `gif`, `ico`, `jpeg`, `png`, or `webp`",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_subsampling(subsampling: &str) -> Result<(), ValidationError> {
+    if !encode::SUBSAMPLINGS.contains(&subsampling) {
+        return Err(ValidationError::new(
+            "Subsampling must be `444`, `422`, or `420`",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_compression(compression: &str) -> Result<(), ValidationError> {
+    if !encode::PNG_COMPRESSIONS.contains(&compression) {
         return Err(ValidationError::new(
-            "Media type must be `jpeg`, `png`, or `webp`",
+            "Compression must be `fast`, `balanced`, or `best`",
         ));
     }
     Ok(())
 }
 
+fn validate_metadata(metadata: &str) -> Result<(), ValidationError> {
+    if !metadata::METADATA.contains(&metadata) {
+        return Err(ValidationError::new(
+            "Metadata must be `strip`, `keep`, or `copyright`",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_bgcolor(bgcolor: &str) -> Result<(), ValidationError> {
+    if Color::from_str(bgcolor).is_err() {
+        return Err(ValidationError::new(
+            "bgcolor must be a hex color such as `ffffff`",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_frame(frame: &str) -> Result<(), ValidationError> {
+    if FrameSelector::from_str(frame).is_err() {
+        return Err(ValidationError::new(
+            "frame must be an index, `middle`, or `last`",
+        ));
+    }
+    Ok(())
+}
+
+const SOURCE_PATH: &str = "data/deventer.jpg";
+
+// Whether the source's format is detected, and accepted by the organization
+fn is_accepted(source_bytes: &[u8], organization: &Organization) -> bool {
+    source::detect(source_bytes).is_some_and(|format| organization.input_formats.contains(&format))
+}
+
+// Options that are only supported for still images, which only become known
+// to be animated once the source has been read
+// `w` may come from client hints, so it's checked once they're resolved
+fn validate_requested(requested: &hints::Dimensions, resize: &str) -> Result<(), &'static str> {
+    match (requested.w, requested.h) {
+        (None, None) => Err("At least one of `w`, `h` must be provided"),
+        (None, Some(_)) if resize == "crop" => {
+            Err("For resize `crop` both `w` and `h` must be provided")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_animated(query_info: &QueryInfo) -> Result<(), &'static str> {
+    if query_info.max_bytes.is_some() {
+        return Err("`max_bytes` is not supported for animated output");
+    }
+    if query_info.quality.as_deref() == Some(QueryInfo::AUTO_QUALITY) {
+        return Err("Quality `auto` is not supported for animated output");
+    }
+    Ok(())
+}
+
+// Invalid ids and settings fail the request, rather than falling back to
+// defaults that may be less restrictive
+fn load_error(e: LoadError) -> HttpResponse {
+    match e {
+        LoadError::InvalidId => HttpResponse::NotFound().body(e.to_string()),
+        LoadError::InvalidSettings(_) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Rejects output the encoder for `media_type` can't handle, as ICO output of
+// more than 256x256 pixels
+fn validate_dimensions(media_type: &MediaType, (w, h): (u32, u32)) -> Result<(), String> {
+    match encode::max_dimension(media_type) {
+        Some(max) if w.max(h) > max => Err(format!(
+            "{media_type:?} images can't exceed {max}x{max} pixels"
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Sizes are validated against the limits of every media type, so this is
+// only reached if an encoder fails unexpectedly
+fn encoding_failed(e: ImageError) -> HttpResponse {
+    HttpResponse::InternalServerError().body(format!("Image could not be encoded: {e}"))
+}
+
+fn unsupported_media_type() -> HttpResponse {
+    HttpResponse::UnsupportedMediaType().body("Source format is not supported")
+}
+
+// A decoded source. Vector images are only rasterized once the geometry is
+// known.
+enum Source {
+    Raster(DynamicImage),
+    Vector(Box<Svg>),
+}
+
+impl Source {
+    fn dimensions(&self) -> (u32, u32) {
+        match self {
+            Self::Raster(image) => image.dimensions(),
+            Self::Vector(svg) => svg.dimensions(),
+        }
+    }
+}
+
+// Decodes the source, converting it to sRGB if it has an ICC profile
+fn decode_source(source_bytes: &[u8], embedded: &mut Embedded) -> Option<Source> {
+    if svg::is_svg(source_bytes) {
+        return Svg::parse(source_bytes).map(|svg| Source::Vector(Box::new(svg)));
+    }
+    let image = match embedded
+        .icc_profile
+        .as_deref()
+        .and_then(|icc_profile| color::decode_srgb(source_bytes, icc_profile))
+    {
+        Some(converted) => {
+            // The pixels are sRGB now, which is what untagged images are
+            // assumed to be
+            embedded.icc_profile = None;
+            converted
+        }
+        None => source::decode(source_bytes).ok()?,
+    };
+    Some(Source::Raster(metadata::orient(
+        image,
+        metadata::orientation(source_bytes),
+    )))
+}
+
+// The resize and crop tuple for the requested geometry. `resize` must be
+// validated, and both `w` and `h` must be given for `crop`.
+fn geometry(
+    image_box: &calc::Box,
+    resize: &str,
+    w: Option<u32>,
+    h: Option<u32>,
+    focal_point: &calc::RelativePoint,
+    zoom: &Option<f64>,
+) -> (calc::Box, calc::CropBox) {
+    match resize {
+        "fit" => calc::fit(
+            image_box,
+            &calc::OptionBox::build(w, h).unwrap(),
+            focal_point,
+            zoom,
+        ),
+        _ => calc::crop(
+            image_box,
+            &calc::Box {
+                w: w.unwrap(),
+                h: h.unwrap(),
+            },
+            focal_point,
+            zoom,
+        ),
+    }
+}
+
+// As `geometry`, for endpoints where all parameters are optional. Without `w`
+// and `h`, the geometry covers the whole source.
+fn optional_geometry(
+    source: &Source,
+    resize: Option<&str>,
+    w: Option<u32>,
+    h: Option<u32>,
+    (fx, fy): (Option<f64>, Option<f64>),
+    zoom: &Option<f64>,
+) -> (calc::Box, calc::CropBox) {
+    let (source_w, source_h) = source.dimensions();
+    let focal_point = calc::RelativePoint::build(
+        fx.unwrap_or(QueryInfo::DEFAULT_FX),
+        fy.unwrap_or(QueryInfo::DEFAULT_FY),
+    )
+    .unwrap();
+    geometry(
+        &calc::Box {
+            w: source_w,
+            h: source_h,
+        },
+        resize.unwrap_or(QueryInfo::DEFAULT_RESIZE),
+        w.or(h.is_none().then_some(source_w)),
+        h,
+        &focal_point,
+        zoom,
+    )
+}
+
+// Applies the geometry computed by `calc::fit` or `calc::crop`
+fn resize_and_crop(
+    source: &Source,
+    (resize_box, crop_box): &(calc::Box, calc::CropBox),
+    linear: bool,
+) -> RgbaImage {
+    let mut resized = match source {
+        Source::Raster(image) => resize::resize(image, resize_box.w, resize_box.h, linear),
+        Source::Vector(svg) => svg.render(resize_box.w, resize_box.h),
+    };
+    crop(&mut resized, crop_box)
+}
+
+// As `resize_and_crop`, keeping 16 bits per channel
+fn resize_and_crop16(
+    image: &DynamicImage,
+    (resize_box, crop_box): &(calc::Box, calc::CropBox),
+    linear: bool,
+) -> Rgba16Image {
+    let mut resized = resize::resize16(image, resize_box.w, resize_box.h, linear);
+    crop(&mut resized, crop_box)
+}
+
+fn crop<P: Pixel + 'static>(
+    image: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    crop_box: &calc::CropBox,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    imageops::crop(
+        image,
+        crop_box.top,
+        crop_box.left,
+        crop_box.bottom - crop_box.top,
+        crop_box.right - crop_box.left,
+    )
+    .to_image()
+}
+
 #[get("/{signature}/{organization_id}/{media_id}")]
-async fn transcode(query: Query<QueryInfo>, path: web::Path<PathInfo>) -> impl Responder {
+async fn transcode(
+    req: HttpRequest,
+    query: Query<QueryInfo>,
+    path: web::Path<PathInfo>,
+) -> impl Responder {
+    let organization = match Organization::load(&path.organization_id) {
+        Ok(organization) => organization,
+        Err(e) => return load_error(e),
+    };
     let resize = query
         .resize
         .to_owned()
         .unwrap_or(QueryInfo::DEFAULT_RESIZE.to_owned());
-    // let media_type = match &query.media_type {
-    //     Some(m) => MediaType::from_str(m).unwrap(),
-    //     None => MediaType::DEFAULT,
-    // };
-    // let default_quality = QueryInfo::get_default_quality_for_media_type(&media_type);
-    // let quality = if default_quality.is_err() {
-    //     None
-    // } else {
-    //     Some(query.quality.unwrap_or_else(|| default_quality.unwrap()))
-    // };
+    let requested = hints::resolve(
+        query.w,
+        query.h,
+        query.dpr,
+        &ClientHints::from_headers(req.headers()),
+        &organization.breakpoints,
+    );
+    if let Err(message) = validate_requested(&requested, &resize) {
+        return HttpResponse::BadRequest()
+            .append_header(("Accept-CH", hints::ACCEPT_CH))
+            .body(message);
+    }
     let fx = query.fx.unwrap_or(QueryInfo::DEFAULT_FX);
     let fy = query.fy.unwrap_or(QueryInfo::DEFAULT_FY);
 
-    let mut source = ImageReader::open("data/deventer.jpg")
-        .unwrap()
-        .decode()
-        .unwrap();
+    let source_bytes = std::fs::read(SOURCE_PATH).unwrap();
+    if !is_accepted(&source_bytes, &organization) {
+        return unsupported_media_type();
+    }
+    let mut embedded = metadata::read(
+        &source_bytes,
+        query
+            .metadata
+            .as_deref()
+            .map_or(Metadata::default(), |m| Metadata::from_str(m).unwrap()),
+    );
+    let icc_profile = embedded.icc_profile.clone();
+    let Some(mut source) = decode_source(&source_bytes, &mut embedded) else {
+        return unsupported_media_type();
+    };
+    // Only the first frame of an animated source is decoded above, other
+    // frames are converted to sRGB from the same profile. TIFF pages are
+    // untagged, as their profiles aren't read.
+    let converted_from = icc_profile.filter(|_| embedded.icc_profile.is_none());
+    let to_srgb = |frame: &RgbaImage| {
+        converted_from
+            .as_deref()
+            .and_then(|icc_profile| color::convert_srgb(frame, icc_profile))
+            .unwrap_or_else(|| frame.clone())
+    };
+    // A single page of a multi-page source
+    if let Some(page) = query.page.map(|page| page as usize) {
+        if page >= pages::page_count(&source_bytes) {
+            return HttpResponse::UnprocessableEntity().body("`page` is out of range");
+        }
+        if page > 0 {
+            let Some(image) = pages::decode_page(&source_bytes, page) else {
+                return HttpResponse::UnsupportedMediaType()
+                    .body("Page format is not supported, e.g. bilevel (fax) pages");
+            };
+            source = Source::Raster(image);
+        }
+    }
+    // A single frame of an animated source, which is then treated as a still
+    // image
+    if let Some(frame) = query.frame.as_deref() {
+        let animation = animation::decode(&source_bytes);
+        let frames = animation.as_ref().map_or(1, |a| a.frames.len());
+        let Some(index) = FrameSelector::from_str(frame).unwrap().index(frames) else {
+            return HttpResponse::UnprocessableEntity().body("`frame` is out of range");
+        };
+        if let Some(animation) = animation {
+            source = Source::Raster(DynamicImage::ImageRgba8(to_srgb(
+                &animation.frames[index].image,
+            )));
+        }
+    }
     let dimensions = source.dimensions();
 
     let image_box = calc::Box {
@@ -160,53 +532,646 @@ async fn transcode(query: Query<QueryInfo>, path: web::Path<PathInfo>) -> impl R
     };
     let focal_point = calc::RelativePoint::build(fx, fy).unwrap();
 
-    let result = match resize.as_str() {
-        "fit" => calc::fit(
-            &image_box,
-            &calc::OptionBox::build(query.w, query.h).unwrap(),
-            &focal_point,
-            &query.zoom,
-        ),
-        _ => calc::crop(
-            &image_box,
-            &calc::Box {
-                w: query.w.unwrap(),
-                h: query.h.unwrap(),
-            },
-            &focal_point,
-            &query.zoom,
+    let result = geometry(
+        &image_box,
+        &resize,
+        requested.w,
+        requested.h,
+        &focal_point,
+        &query.zoom,
+    );
+    // Resizing is costly at sizes beyond an encoder's limits, so these are
+    // checked up front unless the media type depends on the resized image
+    let requested_media_type = match query.media_type.as_deref() {
+        Some(media_type::AUTO) => None,
+        Some(m) => Some(MediaType::from_str(m).unwrap()),
+        None => Some(MediaType::DEFAULT),
+    };
+    if let Some(media_type) = &requested_media_type {
+        let crop_box = &result.1;
+        let size = (
+            crop_box.bottom - crop_box.top,
+            crop_box.right - crop_box.left,
+        );
+        if let Err(e) = validate_dimensions(media_type, size) {
+            return HttpResponse::UnprocessableEntity().body(e);
+        }
+    }
+
+    // Applied to the source, or to every frame of an animated source
+    let linear = query.linear.unwrap_or(organization.linear_resize);
+    let transform = |source: &Source| resize_and_crop(source, &result, linear);
+    // Sources with more than 8 bits per channel are resized in 16 bits, which
+    // PNG output keeps
+    let deep = match &source {
+        Source::Raster(image) if resize::is_high_bit_depth(image) => {
+            Some(resize_and_crop16(image, &result, linear))
+        }
+        _ => None,
+    };
+    let cropped = match &deep {
+        Some(deep) => DynamicImage::ImageRgba16(deep.clone()).into_rgba8(),
+        None => transform(&source),
+    };
+
+    let mut vary = requested.vary;
+    let media_type = match requested_media_type {
+        Some(media_type) => media_type,
+        None => {
+            vary.push("Accept");
+            let accept = req
+                .headers()
+                .get("Accept")
+                .and_then(|accept| accept.to_str().ok())
+                .unwrap_or_default();
+            let media_type = media_type::negotiate(accept, encode::has_alpha(&cropped));
+            if let Err(e) = validate_dimensions(&media_type, cropped.dimensions()) {
+                return HttpResponse::UnprocessableEntity().body(e);
+            }
+            media_type
+        }
+    };
+    // Only GIF and WebP output can be animated, other media types get the
+    // first frame. Frames are counted from the headers, so that requests
+    // animated output can't satisfy are rejected before decoding every frame.
+    let animated = matches!(media_type, MediaType::GIF | MediaType::WEBP)
+        && query.frame.is_none()
+        && animation::frame_count(&source_bytes) > 1;
+    if animated {
+        if let Err(message) = validate_animated(&query) {
+            return HttpResponse::UnprocessableEntity().body(message);
+        }
+    }
+    let animation = if animated {
+        animation::decode(&source_bytes).map(|animation| {
+            animation
+                .map(|frame| transform(&Source::Raster(DynamicImage::ImageRgba8(to_srgb(frame)))))
+        })
+    } else {
+        None
+    };
+    // JPEG has no alpha channel
+    let cropped = match media_type {
+        MediaType::JPEG if encode::has_alpha(&cropped) => color::flatten(
+            &cropped,
+            query
+                .bgcolor
+                .as_deref()
+                .map_or(organization.bgcolor, |c| Color::from_str(c).unwrap()),
         ),
+        _ => cropped,
+    };
+    // Unless the PNG is quantized
+    let deep = deep.filter(|_| media_type == MediaType::PNG && query.colors.is_none());
+    let lossless = query.lossless.unwrap_or(false);
+    // With `media_type=auto`, quality `auto` falls back to the default quality
+    // for media types that don't support it
+    let auto_quality = query.quality.as_deref() == Some(QueryInfo::AUTO_QUALITY)
+        && matches!(media_type, MediaType::JPEG | MediaType::WEBP);
+    let quality = QueryInfo::get_default_quality_for_media_type(&media_type)
+        .ok()
+        .filter(|_| !lossless)
+        .map(|default_quality| {
+            query
+                .quality
+                .as_deref()
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(default_quality)
+        });
+
+    let mut options = encode::Options {
+        quality,
+        speed: query.speed,
+        lossless,
+        progressive: query.progressive.unwrap_or(false),
+        subsampling: query
+            .subsampling
+            .as_deref()
+            .map(|s| encode::Subsampling::from_str(s).unwrap()),
+        colors: query.colors,
+        dither: query.dither.unwrap_or(false),
+        png_compression: query
+            .compression
+            .as_deref()
+            .map_or(organization.png_compression, |c| {
+                encode::PngCompression::from_str(c).unwrap()
+            }),
+        metadata: embedded,
+    };
+
+    let mut response = HttpResponse::Ok();
+    let mut perceptual = None;
+    if auto_quality {
+        let fitted = match encode::encode_perceptual(
+            &cropped,
+            &media_type,
+            &options,
+            organization.ssim_target,
+        ) {
+            Ok(fitted) => fitted,
+            Err(e) => return encoding_failed(e),
+        };
+        // Serves as the upper bound if `max_bytes` is given as well
+        options.quality = Some(fitted.quality);
+        perceptual = Some(fitted);
+    }
+    let encoded = match (&animation, query.max_bytes) {
+        (Some(animation), _) => encode::encode_animation(animation, &media_type, &options),
+        (None, Some(max_bytes)) => {
+            match encode::encode_within(&cropped, &media_type, &options, max_bytes as usize, linear)
+            {
+                Ok(Some(fitted)) => {
+                    response.append_header(("X-Quality", fitted.quality.to_string()));
+                    Ok(fitted.bytes)
+                }
+                Ok(None) => {
+                    return HttpResponse::UnprocessableEntity()
+                        .body("Image cannot be encoded within `max_bytes`");
+                }
+                Err(e) => Err(e),
+            }
+        }
+        (None, None) => match (perceptual, &deep) {
+            (Some(fitted), _) => {
+                response.append_header(("X-Quality", fitted.quality.to_string()));
+                Ok(fitted.bytes)
+            }
+            (None, Some(deep)) => encode::encode_png16(deep, &options),
+            (None, None) => encode::encode(&cropped, &media_type, &options),
+        },
+    };
+    let bytes = match encoded {
+        Ok(bytes) => bytes,
+        Err(e) => return encoding_failed(e),
+    };
+
+    response
+        .append_header(("Content-Type", media_type.mime_type()))
+        .append_header(("Accept-CH", hints::ACCEPT_CH));
+    if !vary.is_empty() {
+        response.append_header(("Vary", vary.join(", ")));
+    }
+    response.body(bytes)
+}
+
+#[derive(Deserialize, Validate, Debug)]
+struct FaviconQueryInfo {
+    #[validate(range(min = 0.5, max = 2.))]
+    zoom: Option<f64>,
+    linear: Option<bool>,
+    #[validate(range(min = 0., max = 100.))]
+    fx: Option<f64>,
+    #[validate(range(min = 0., max = 100.))]
+    fy: Option<f64>,
+}
+
+impl FaviconQueryInfo {
+    // Width and height of every image in the ICO
+    const SIZES: [u32; 5] = [16, 32, 48, 64, 256];
+}
+
+#[get("/{signature}/{organization_id}/{media_id}/favicon.ico")]
+async fn favicon(query: Query<FaviconQueryInfo>, path: web::Path<PathInfo>) -> impl Responder {
+    let organization = match Organization::load(&path.organization_id) {
+        Ok(organization) => organization,
+        Err(e) => return load_error(e),
+    };
+    let source_bytes = std::fs::read(SOURCE_PATH).unwrap();
+    if !is_accepted(&source_bytes, &organization) {
+        return unsupported_media_type();
+    }
+    let Some(source) = decode_source(
+        &source_bytes,
+        &mut metadata::read(&source_bytes, Metadata::Strip),
+    ) else {
+        return unsupported_media_type();
+    };
+    let (w, h) = source.dimensions();
+    let image_box = calc::Box { w, h };
+    let focal_point = calc::RelativePoint::build(
+        query.fx.unwrap_or(QueryInfo::DEFAULT_FX),
+        query.fy.unwrap_or(QueryInfo::DEFAULT_FY),
+    )
+    .unwrap();
+    let linear = query.linear.unwrap_or(organization.linear_resize);
+
+    let images: Vec<RgbaImage> = FaviconQueryInfo::SIZES
+        .into_iter()
+        .map(|size| {
+            let result = calc::crop(
+                &image_box,
+                &calc::Box { w: size, h: size },
+                &focal_point,
+                &query.zoom,
+            );
+            resize_and_crop(&source, &result, linear)
+        })
+        .collect();
+
+    match encode::encode_ico(&images) {
+        Ok(bytes) => HttpResponse::Ok()
+            .append_header(("Content-Type", MediaType::ICO.mime_type()))
+            .body(bytes),
+        Err(e) => encoding_failed(e),
+    }
+}
+
+#[derive(Deserialize, Validate, Debug)]
+#[validate(schema(function = "validate_placeholder_query_info"))]
+struct PlaceholderQueryInfo {
+    #[validate(custom = "validate_resize")]
+    resize: Option<String>,
+    // The geometry of the image the placeholder stands in for. Without `w`
+    // and `h`, the placeholder is computed for the whole source.
+    w: Option<u32>,
+    h: Option<u32>,
+    #[validate(range(min = 0.5, max = 2.))]
+    zoom: Option<f64>,
+    linear: Option<bool>,
+    #[validate(range(min = 0., max = 100.))]
+    fx: Option<f64>,
+    #[validate(range(min = 0., max = 100.))]
+    fy: Option<f64>,
+    // Number of horizontal and vertical BlurHash components
+    #[validate(range(min = 1, max = "placeholder::MAX_COMPONENTS"))]
+    x_components: Option<u32>,
+    #[validate(range(min = 1, max = "placeholder::MAX_COMPONENTS"))]
+    y_components: Option<u32>,
+    // A low-quality image placeholder instead of a BlurHash
+    lqip: Option<bool>,
+    // Media type of the low-quality image placeholder
+    #[validate(custom = "validate_lqip_media_type")]
+    media_type: Option<String>,
+}
+
+fn validate_lqip_media_type(media_type: &str) -> Result<(), ValidationError> {
+    if !MediaType::from_str(media_type).is_ok_and(|m| placeholder::LQIP_MEDIA_TYPES.contains(&m)) {
+        return Err(ValidationError::new("Media type must be `jpeg` or `webp`"));
+    }
+    Ok(())
+}
+
+// For endpoints where `w` and `h` are optional even with resize `crop`
+fn validate_crop(
+    resize: &Option<String>,
+    w: Option<u32>,
+    h: Option<u32>,
+) -> Result<(), ValidationError> {
+    if resize.as_deref() == Some("crop") && (w.is_none() || h.is_none()) {
+        return Err(ValidationError::new(
+            "For resize `crop` both `w` and `h` must be provided",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_placeholder_query_info(
+    query_info: &PlaceholderQueryInfo,
+) -> Result<(), ValidationError> {
+    validate_crop(&query_info.resize, query_info.w, query_info.h)?;
+    let lqip = query_info.lqip.unwrap_or(false);
+    if lqip && (query_info.x_components.is_some() || query_info.y_components.is_some()) {
+        return Err(ValidationError::new(
+            "Components cannot be combined with `lqip`",
+        ));
+    }
+    if !lqip && query_info.media_type.is_some() {
+        return Err(ValidationError::new("`media_type` requires `lqip`"));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+struct Placeholder {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blurhash: Option<String>,
+    // A `data:` URI
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lqip: Option<String>,
+    // Dimensions of the image the placeholder stands in for
+    width: u32,
+    height: u32,
+}
+
+#[get("/{signature}/{organization_id}/{media_id}/placeholder")]
+async fn get_placeholder(
+    req: HttpRequest,
+    query: Query<PlaceholderQueryInfo>,
+    path: web::Path<PathInfo>,
+) -> impl Responder {
+    let organization = match Organization::load(&path.organization_id) {
+        Ok(organization) => organization,
+        Err(e) => return load_error(e),
+    };
+    let source_bytes = std::fs::read(SOURCE_PATH).unwrap();
+    if !is_accepted(&source_bytes, &organization) {
+        return unsupported_media_type();
+    }
+    let Some(source) = decode_source(
+        &source_bytes,
+        &mut metadata::read(&source_bytes, Metadata::Strip),
+    ) else {
+        return unsupported_media_type();
     };
+    let result = optional_geometry(
+        &source,
+        query.resize.as_deref(),
+        query.w,
+        query.h,
+        (query.fx, query.fy),
+        &query.zoom,
+    );
+    let (_, crop_box) = &result;
+    let (width, height) = (
+        crop_box.bottom - crop_box.top,
+        crop_box.right - crop_box.left,
+    );
+
+    let linear = query.linear.unwrap_or(organization.linear_resize);
+    if query.lqip == Some(true) {
+        let media_type = query
+            .media_type
+            .as_deref()
+            .map_or(MediaType::DEFAULT, |m| MediaType::from_str(m).unwrap());
+        let small = calc::scale_down(&result, placeholder::LQIP_DIMENSION);
+        let image = resize_and_crop(&source, &small, linear);
+        // JPEG has no alpha channel
+        let image = match media_type {
+            MediaType::JPEG => color::flatten(&image, organization.bgcolor),
+            _ => image,
+        };
+        let lqip = placeholder::lqip(&image, &media_type).unwrap();
+        // The data URI as is, ready to be inlined, unless JSON is asked for
+        let accept = req
+            .headers()
+            .get("Accept")
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or_default();
+        let mut response = HttpResponse::Ok();
+        response.append_header(("Vary", "Accept"));
+        if media_type::accepts(accept, "application/json") {
+            return response.json(Placeholder {
+                blurhash: None,
+                lqip: Some(lqip),
+                width,
+                height,
+            });
+        }
+        return response.content_type("text/plain").body(lqip);
+    }
 
-    let mut resized = imageops::resize(
-        &mut source,
-        result.0.w,
-        result.0.h,
-        imageops::FilterType::CatmullRom,
+    // Computed from a small version of the final image, with transparent
+    // areas shown on the organization's background color
+    let small = calc::scale_down(&result, placeholder::MAX_DIMENSION);
+    let image = color::flatten(
+        &resize_and_crop(&source, &small, linear),
+        organization.bgcolor,
     );
-    let cropped = imageops::crop(
-        &mut resized,
-        result.1.top,
-        result.1.left,
-        result.1.bottom - result.1.top,
-        result.1.right - result.1.left,
+    let (x_components, y_components) = placeholder::DEFAULT_COMPONENTS;
+    let blurhash = placeholder::blurhash(
+        &image,
+        query.x_components.unwrap_or(x_components),
+        query.y_components.unwrap_or(y_components),
     )
-    .to_image();
+    .unwrap();
+
+    HttpResponse::Ok().json(Placeholder {
+        blurhash: Some(blurhash),
+        lqip: None,
+        width,
+        height,
+    })
+}
+
+#[derive(Deserialize, Validate, Debug)]
+#[validate(schema(function = "validate_palette_query_info"))]
+struct PaletteQueryInfo {
+    #[validate(custom = "validate_resize")]
+    resize: Option<String>,
+    // The geometry of the image to extract the palette from. Without `w` and
+    // `h`, the palette is extracted from the whole source.
+    w: Option<u32>,
+    h: Option<u32>,
+    #[validate(range(min = 0.5, max = 2.))]
+    zoom: Option<f64>,
+    #[validate(range(min = 0., max = 100.))]
+    fx: Option<f64>,
+    #[validate(range(min = 0., max = 100.))]
+    fy: Option<f64>,
+    #[validate(range(min = 1, max = "palette::MAX_COLORS"))]
+    colors: Option<u16>,
+}
+
+fn validate_palette_query_info(query_info: &PaletteQueryInfo) -> Result<(), ValidationError> {
+    validate_crop(&query_info.resize, query_info.w, query_info.h)
+}
+
+#[derive(Serialize, Debug)]
+struct Palette {
+    dominant: Color,
+    palette: Vec<Swatch>,
+}
+
+#[get("/{signature}/{organization_id}/{media_id}/palette")]
+async fn get_palette(query: Query<PaletteQueryInfo>, path: web::Path<PathInfo>) -> impl Responder {
+    let organization = match Organization::load(&path.organization_id) {
+        Ok(organization) => organization,
+        Err(e) => return load_error(e),
+    };
+    let source_bytes = std::fs::read(SOURCE_PATH).unwrap();
+    if !is_accepted(&source_bytes, &organization) {
+        return unsupported_media_type();
+    }
+    let Some(source) = decode_source(
+        &source_bytes,
+        &mut metadata::read(&source_bytes, Metadata::Strip),
+    ) else {
+        return unsupported_media_type();
+    };
+    let result = optional_geometry(
+        &source,
+        query.resize.as_deref(),
+        query.w,
+        query.h,
+        (query.fx, query.fy),
+        &query.zoom,
+    );
+    let small = calc::scale_down(&result, palette::MAX_DIMENSION);
+    let image = resize_and_crop(&source, &small, organization.linear_resize);
+
+    let swatches = palette::palette(&image, query.colors.unwrap_or(palette::DEFAULT_COLORS));
+    // Sources that are transparent throughout have no colors
+    let Some(dominant) = swatches.first().map(|swatch| swatch.color) else {
+        return HttpResponse::UnprocessableEntity().body("Image has no visible pixels");
+    };
+    HttpResponse::Ok().json(Palette {
+        dominant,
+        palette: swatches,
+    })
+}
+
+#[derive(Serialize, Debug)]
+struct MediaInfo {
+    format: InputFormat,
+    width: u32,
+    height: u32,
+    // Pages of a multi-page TIFF, 1 for other formats
+    pages: usize,
+    // Frames of an animated GIF or WebP, 1 for other formats
+    frames: usize,
+}
 
-    let mut bytes: Vec<u8> = Vec::new();
-    cropped
-        .write_to(&mut Cursor::new(&mut bytes), image::ImageOutputFormat::Png)
-        .unwrap();
+#[get("/{signature}/{organization_id}/{media_id}/info")]
+async fn info(path: web::Path<PathInfo>) -> impl Responder {
+    let organization = match Organization::load(&path.organization_id) {
+        Ok(organization) => organization,
+        Err(e) => return load_error(e),
+    };
+    let source_bytes = std::fs::read(SOURCE_PATH).unwrap();
+    if !is_accepted(&source_bytes, &organization) {
+        return unsupported_media_type();
+    }
+    // Everything is read from the headers, without decoding any pixels
+    let dimensions = if svg::is_svg(&source_bytes) {
+        Svg::parse(&source_bytes).map(|svg| svg.dimensions())
+    } else {
+        source::dimensions(&source_bytes).ok()
+    };
+    let Some((width, height)) = dimensions else {
+        return unsupported_media_type();
+    };
+    // Orientations 5 to 8 rotate the image by 90 degrees
+    let (width, height) = match metadata::orientation(&source_bytes) {
+        5..=8 => (height, width),
+        _ => (width, height),
+    };
 
-    HttpResponse::Ok()
-        .append_header(("Content-Type", "image/png"))
-        .body(bytes)
+    HttpResponse::Ok().json(MediaInfo {
+        format: source::detect(&source_bytes).unwrap(),
+        width,
+        height,
+        pages: pages::page_count(&source_bytes),
+        frames: animation::frame_count(&source_bytes),
+    })
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(|| App::new().service(transcode))
-        .bind(("127.0.0.1", 8080))?
-        .run()
-        .await
+    HttpServer::new(|| {
+        App::new()
+            .service(transcode)
+            .service(favicon)
+            .service(get_placeholder)
+            .service(get_palette)
+            .service(info)
+    })
+    .bind(("127.0.0.1", 8080))?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(query: &str) -> QueryInfo {
+        web::Query::<QueryInfo>::from_query(query)
+            .unwrap()
+            .into_inner()
+    }
+
+    #[test]
+    fn test_validate_animated() {
+        assert!(validate_animated(&query("w=100&media_type=webp&quality=50")).is_ok());
+    }
+
+    #[test]
+    fn test_validate_animated_rejects_max_bytes() {
+        assert!(validate_animated(&query("w=100&media_type=webp&max_bytes=1000")).is_err());
+    }
+
+    #[test]
+    fn test_validate_animated_rejects_auto_quality() {
+        assert!(validate_animated(&query("w=100&media_type=webp&quality=auto")).is_err());
+    }
+
+    #[test]
+    fn test_validate_dimensions() {
+        assert!(validate_dimensions(&MediaType::WEBP, (16383, 100)).is_ok());
+        assert!(validate_dimensions(&MediaType::WEBP, (100, 16384)).is_err());
+        assert!(validate_dimensions(&MediaType::JPEG, (20000, 100)).is_ok());
+        assert!(validate_dimensions(&MediaType::ICO, (257, 1)).is_err());
+        assert!(validate_dimensions(&MediaType::PNG, (100000, 1)).is_ok());
+    }
+
+    fn requested(w: Option<u32>, h: Option<u32>) -> hints::Dimensions {
+        hints::Dimensions { w, h, vary: vec![] }
+    }
+
+    #[test]
+    fn test_validate_requested() {
+        assert!(validate_requested(&requested(Some(100), None), "fit").is_ok());
+        assert!(validate_requested(&requested(None, Some(100)), "fit").is_ok());
+        assert!(validate_requested(&requested(Some(100), Some(100)), "crop").is_ok());
+        assert_eq!(
+            validate_requested(&requested(None, None), "fit"),
+            Err("At least one of `w`, `h` must be provided")
+        );
+    }
+
+    #[test]
+    fn test_validate_requested_crop_without_width() {
+        assert_eq!(
+            validate_requested(&requested(None, Some(200)), "crop"),
+            Err("For resize `crop` both `w` and `h` must be provided")
+        );
+    }
+
+    #[test]
+    fn test_validate_lossless() {
+        assert!(validate_query_info(&query("w=100&lossless=true")).is_ok());
+        assert!(validate_query_info(&query("w=100&media_type=png&lossless=true")).is_err());
+    }
+
+    #[test]
+    fn test_validate_lossless_rejects_quality() {
+        assert!(validate_query_info(&query("w=100&lossless=true&quality=80")).is_err());
+    }
+
+    #[test]
+    fn test_validate_jpeg_options() {
+        let valid = "w=100&media_type=jpeg&progressive=true&subsampling=444";
+        assert!(validate_query_info(&query(valid)).is_ok());
+    }
+
+    #[test]
+    fn test_validate_jpeg_options_require_jpeg() {
+        assert!(validate_query_info(&query("w=100&media_type=png&progressive=true")).is_err());
+        assert!(validate_query_info(&query("w=100&subsampling=420")).is_err());
+    }
+
+    #[test]
+    fn test_validate_colors() {
+        let valid = "w=100&media_type=png&colors=16&dither=true";
+        assert!(validate_query_info(&query(valid)).is_ok());
+        assert!(validate_query_info(&query("w=100&media_type=jpeg&colors=16")).is_err());
+    }
+
+    #[test]
+    fn test_validate_dither_requires_colors() {
+        assert!(validate_query_info(&query("w=100&media_type=png&dither=true")).is_err());
+    }
+
+    #[test]
+    fn test_validate_compression() {
+        assert!(validate_query_info(&query("w=100&media_type=png&compression=best")).is_ok());
+        assert!(validate_query_info(&query("w=100&compression=best")).is_err());
+    }
+
+    #[test]
+    fn test_validate_gif_colors() {
+        assert!(validate_query_info(&query("w=100&media_type=gif&colors=16")).is_ok());
+        // GIF output is always quantized
+        assert!(validate_query_info(&query("w=100&media_type=gif&dither=true")).is_ok());
+    }
 }
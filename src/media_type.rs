@@ -0,0 +1,140 @@
+//! Output media types, and content negotiation for `media_type=auto`.
+
+use std::str::FromStr;
+
+pub const MEDIA_TYPES: [&str; 6] = ["avif", "gif", "ico", "jpeg", "png", "webp"];
+
+/// Media type parameter value that picks the media type based on the request's
+/// `Accept` header.
+pub const AUTO: &str = "auto";
+
+#[derive(Debug, PartialEq)]
+pub enum MediaType {
+    AVIF,
+    GIF,
+    ICO,
+    JPEG,
+    WEBP,
+    PNG,
+}
+
+impl MediaType {
+    pub const DEFAULT: Self = Self::WEBP;
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::AVIF => "image/avif",
+            Self::GIF => "image/gif",
+            Self::ICO => "image/x-icon",
+            Self::JPEG => "image/jpeg",
+            Self::PNG => "image/png",
+            Self::WEBP => "image/webp",
+        }
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<MediaType, Self::Err> {
+        match input {
+            "avif" => Ok(Self::AVIF),
+            "gif" => Ok(Self::GIF),
+            "ico" => Ok(Self::ICO),
+            "jpeg" => Ok(Self::JPEG),
+            "png" => Ok(Self::PNG),
+            "webp" => Ok(Self::WEBP),
+            _ => Err(()),
+        }
+    }
+}
+
+pub const DEFAULT_MEDIA_TYPE: MediaType = MediaType::JPEG;
+
+pub const DEFAULT_QUALITY: [(MediaType, u8); 3] = [
+    (MediaType::AVIF, 55),
+    (MediaType::JPEG, 70),
+    (MediaType::WEBP, 60),
+];
+
+/// Picks the most efficient media type the browser accepts, falling back to
+/// JPEG, or PNG if the image has transparent pixels.
+///
+/// # Examples
+///
+/// ```
+/// use imgconv::media_type::{negotiate, MediaType};
+/// let accept = "image/webp,image/apng,image/*,*/*;q=0.8";
+/// assert_eq!(negotiate(accept, false), MediaType::WEBP);
+/// assert_eq!(negotiate("image/avif,image/webp", false), MediaType::AVIF);
+/// assert_eq!(negotiate("image/*", true), MediaType::PNG);
+/// ```
+pub fn negotiate(accept: &str, has_alpha: bool) -> MediaType {
+    if accepts(accept, MediaType::AVIF.mime_type()) {
+        return MediaType::AVIF;
+    }
+    if accepts(accept, MediaType::WEBP.mime_type()) {
+        return MediaType::WEBP;
+    }
+    if has_alpha {
+        return MediaType::PNG;
+    }
+    MediaType::JPEG
+}
+
+/// Whether `accept` explicitly lists `mime_type`. Wildcards are ignored, since
+/// browsers send `image/*` regardless of which formats they can decode.
+pub fn accepts(accept: &str, mime_type: &str) -> bool {
+    accept.split(',').any(|item| {
+        let mut params = item.split(';').map(str::trim);
+        let matches = params
+            .next()
+            .is_some_and(|m| m.eq_ignore_ascii_case(mime_type));
+        let q = params
+            .filter_map(|param| param.strip_prefix("q="))
+            .find_map(|q| q.parse::<f64>().ok())
+            .unwrap_or(1.);
+        matches && q > 0.
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() {
+        assert!(accepts("image/avif,image/webp,*/*", "image/webp"));
+        assert!(accepts("image/avif, IMAGE/WEBP;q=0.9", "image/webp"));
+    }
+
+    #[test]
+    fn test_accepts_ignores_wildcards() {
+        assert!(!accepts("image/*,*/*;q=0.8", "image/webp"));
+    }
+
+    #[test]
+    fn test_accepts_q_zero() {
+        assert!(!accepts("image/webp;q=0", "image/webp"));
+    }
+
+    #[test]
+    fn test_negotiate_prefers_avif() {
+        assert_eq!(negotiate("image/webp,image/avif", true), MediaType::AVIF);
+    }
+
+    #[test]
+    fn test_negotiate_falls_back_to_jpeg() {
+        assert_eq!(negotiate("image/png,image/*", false), MediaType::JPEG);
+    }
+
+    #[test]
+    fn test_negotiate_alpha_falls_back_to_png() {
+        assert_eq!(negotiate("", true), MediaType::PNG);
+    }
+
+    #[test]
+    fn test_negotiate_webp_supports_alpha() {
+        assert_eq!(negotiate("image/webp", true), MediaType::WEBP);
+    }
+}
@@ -0,0 +1,312 @@
+//! Metadata handling.
+//!
+//! Decoding and re-encoding an image drops all of its metadata, so the ICC
+//! profile and a selection of EXIF tags are read from the source and written
+//! to the output explicitly. Location (GPS) data is never carried over.
+//!
+//! The EXIF orientation is applied to the pixels when decoding, so outputs
+//! are always upright.
+
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Tag, Value};
+use image::DynamicImage;
+use img_parts::{Bytes, DynImage, ImageEXIF, ImageICC};
+use std::io::Cursor;
+use std::str::FromStr;
+
+/// Accepted values of the `metadata` parameter.
+pub const METADATA: [&str; 3] = ["strip", "keep", "copyright"];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Metadata {
+    // Only the ICC profile
+    #[default]
+    Strip,
+    // The ICC profile and all EXIF tags, apart from location data, maker
+    // notes, thumbnails and the tags describing the source's pixels
+    Keep,
+    // The ICC profile and the copyright and artist EXIF tags
+    Copyright,
+}
+
+impl FromStr for Metadata {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Metadata, Self::Err> {
+        match input {
+            "strip" => Ok(Self::Strip),
+            "keep" => Ok(Self::Keep),
+            "copyright" => Ok(Self::Copyright),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Metadata to embed in the output.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Embedded {
+    pub icc_profile: Option<Vec<u8>>,
+    // EXIF data in TIFF format, without the `Exif\0\0` prefix used by JPEG
+    pub exif: Option<Vec<u8>>,
+}
+
+/// Reads the metadata to embed from a JPEG, PNG or WebP source. Sources in
+/// other formats yield no metadata.
+pub fn read(source: &[u8], metadata: Metadata) -> Embedded {
+    let image = match DynImage::from_bytes(Bytes::copy_from_slice(source)) {
+        Ok(Some(image)) => image,
+        _ => return Embedded::default(),
+    };
+    Embedded {
+        icc_profile: image.icc_profile().map(|icc| icc.to_vec()),
+        exif: image.exif().and_then(|exif| filter_exif(&exif, metadata)),
+    }
+}
+
+/// The EXIF orientation of a JPEG, PNG or WebP source, from 1 (upright) to 8.
+/// Sources without a (valid) orientation are upright.
+pub fn orientation(source: &[u8]) -> u32 {
+    let image = match DynImage::from_bytes(Bytes::copy_from_slice(source)) {
+        Ok(Some(image)) => image,
+        _ => return 1,
+    };
+    image
+        .exif()
+        .and_then(|exif| exif::Reader::new().read_raw(exif.to_vec()).ok())
+        .and_then(|exif| {
+            exif.get_field(Tag::Orientation, In::PRIMARY)?
+                .value
+                .get_uint(0)
+        })
+        .filter(|orientation| (1..=8).contains(orientation))
+        .unwrap_or(1)
+}
+
+/// Rotates and flips `image` as described by an EXIF `orientation`, making it
+/// upright.
+///
+/// # Examples
+///
+/// ```
+/// use image::{DynamicImage, RgbaImage};
+/// use imgconv::metadata::orient;
+/// let image = DynamicImage::ImageRgba8(RgbaImage::new(40, 20));
+/// // Rotated 90° clockwise
+/// assert_eq!(orient(image, 6).width(), 20);
+/// ```
+pub fn orient(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// Tags that describe the source's pixels rather than the output's, which may
+// have been resized, cropped or rotated
+const SOURCE_TAGS: [Tag; 6] = [
+    Tag::ImageWidth,
+    Tag::ImageLength,
+    Tag::PixelXDimension,
+    Tag::PixelYDimension,
+    Tag::JPEGInterchangeFormat,
+    Tag::JPEGInterchangeFormatLength,
+];
+
+/// Writes `embedded` into an encoded PNG or WebP image. Images in other
+/// formats are returned unchanged.
+pub fn embed(encoded: Vec<u8>, embedded: &Embedded) -> Vec<u8> {
+    if embedded == &Embedded::default() {
+        return encoded;
+    }
+    let mut image = match DynImage::from_bytes(Bytes::from(encoded.clone())) {
+        Ok(Some(image @ (DynImage::Png(_) | DynImage::WebP(_)))) => image,
+        _ => return encoded,
+    };
+    image.set_icc_profile(embedded.icc_profile.clone().map(Bytes::from));
+    image.set_exif(embedded.exif.clone().map(Bytes::from));
+    image.encoder().bytes().to_vec()
+}
+
+// Rebuilds the EXIF data with only the tags allowed by `metadata`.
+fn filter_exif(exif: &[u8], metadata: Metadata) -> Option<Vec<u8>> {
+    let exif = exif::Reader::new().read_raw(exif.to_vec()).ok()?;
+    // The orientation has been applied to the pixels
+    let upright = Field {
+        tag: Tag::Orientation,
+        ifd_num: In::PRIMARY,
+        value: Value::Short(vec![1]),
+    };
+    let fields: Vec<&Field> = exif
+        .fields()
+        .filter(|field| field.ifd_num == In::PRIMARY)
+        .filter(|field| match metadata {
+            Metadata::Strip => false,
+            Metadata::Keep => {
+                field.tag.context() != Context::Gps
+                    && field.tag != Tag::MakerNote
+                    && !SOURCE_TAGS.contains(&field.tag)
+            }
+            Metadata::Copyright => [Tag::Copyright, Tag::Artist].contains(&field.tag),
+        })
+        .map(|field| match field.tag {
+            Tag::Orientation => &upright,
+            _ => field,
+        })
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+    let mut writer = Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut bytes = Cursor::new(Vec::new());
+    writer.write(&mut bytes, exif.little_endian()).ok()?;
+    Some(bytes.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::Rational;
+
+    fn exif() -> Vec<u8> {
+        let fields = [
+            Field {
+                tag: Tag::Copyright,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"Imgconv".to_vec()]),
+            },
+            Field {
+                tag: Tag::Make,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"Camera".to_vec()]),
+            },
+            Field {
+                tag: Tag::GPSLatitude,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![Rational::from((52, 1)); 3]),
+            },
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![6]),
+            },
+            Field {
+                tag: Tag::PixelXDimension,
+                ifd_num: In::PRIMARY,
+                value: Value::Long(vec![4000]),
+            },
+            // The thumbnail's
+            Field {
+                tag: Tag::ImageWidth,
+                ifd_num: In::THUMBNAIL,
+                value: Value::Long(vec![160]),
+            },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut bytes = Cursor::new(Vec::new());
+        writer.write(&mut bytes, false).unwrap();
+        bytes.into_inner()
+    }
+
+    fn tags(exif: &[u8]) -> Vec<Tag> {
+        let exif = exif::Reader::new().read_raw(exif.to_vec()).unwrap();
+        exif.fields().map(|field| field.tag).collect()
+    }
+
+    #[test]
+    fn test_filter_exif_keep_strips_location() {
+        let filtered = filter_exif(&exif(), Metadata::Keep).unwrap();
+        assert!(!tags(&filtered).contains(&Tag::GPSLatitude));
+    }
+
+    #[test]
+    fn test_filter_exif_keep_strips_source_tags() {
+        let filtered = filter_exif(&exif(), Metadata::Keep).unwrap();
+        assert_eq!(
+            tags(&filtered),
+            vec![Tag::Make, Tag::Orientation, Tag::Copyright]
+        );
+        let exif = exif::Reader::new().read_raw(filtered).unwrap();
+        let orientation = exif.get_field(Tag::Orientation, In::PRIMARY).unwrap();
+        assert_eq!(orientation.value.get_uint(0), Some(1));
+    }
+
+    #[test]
+    fn test_orientation() {
+        let mut png = Vec::new();
+        image::RgbaImage::new(1, 1)
+            .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        assert_eq!(orientation(&png), 1);
+        let embedded = Embedded {
+            icc_profile: None,
+            exif: Some(exif()),
+        };
+        assert_eq!(orientation(&embed(png, &embedded)), 6);
+    }
+
+    #[test]
+    fn test_orient() {
+        // A red pixel at the top left
+        let mut image = image::RgbaImage::new(3, 2);
+        image.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        let red = |image: &DynamicImage| {
+            let image = image.to_rgba8();
+            let (x, y, _) = image
+                .enumerate_pixels()
+                .find(|(_, _, p)| p[0] == 255)
+                .unwrap();
+            (image.width(), image.height(), x, y)
+        };
+        let image = DynamicImage::ImageRgba8(image);
+        assert_eq!(red(&orient(image.clone(), 1)), (3, 2, 0, 0));
+        assert_eq!(red(&orient(image.clone(), 3)), (3, 2, 2, 1));
+        assert_eq!(red(&orient(image.clone(), 5)), (2, 3, 0, 0));
+        assert_eq!(red(&orient(image.clone(), 6)), (2, 3, 1, 0));
+        assert_eq!(red(&orient(image.clone(), 7)), (2, 3, 1, 2));
+        assert_eq!(red(&orient(image, 8)), (2, 3, 0, 2));
+    }
+
+    #[test]
+    fn test_filter_exif_copyright() {
+        let filtered = filter_exif(&exif(), Metadata::Copyright).unwrap();
+        assert_eq!(tags(&filtered), vec![Tag::Copyright]);
+    }
+
+    #[test]
+    fn test_filter_exif_strip() {
+        assert_eq!(filter_exif(&exif(), Metadata::Strip), None);
+    }
+
+    #[test]
+    fn test_embed_and_read_png() {
+        let mut png = Vec::new();
+        image::RgbaImage::new(1, 1)
+            .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        let embedded = Embedded {
+            icc_profile: Some(b"profile".to_vec()),
+            exif: filter_exif(&exif(), Metadata::Keep),
+        };
+        let png = embed(png, &embedded);
+        assert_eq!(read(&png, Metadata::Keep), embedded);
+        assert_eq!(
+            read(&png, Metadata::Strip),
+            Embedded {
+                icc_profile: Some(b"profile".to_vec()),
+                exif: None,
+            }
+        );
+    }
+}
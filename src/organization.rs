@@ -0,0 +1,205 @@
+//! Per-organization settings.
+//!
+//! Settings are read from `data/organizations/{organization_id}.json`. Any
+//! setting that is missing from that file, or a missing file altogether, falls
+//! back to the defaults below. A file that can't be read or parsed is an
+//! error, as falling back would e.g. lift the organization's restrictions on
+//! input formats. So is an organization id that can't name a settings file.
+
+use crate::color::Color;
+use crate::encode::PngCompression;
+use crate::source::InputFormat;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const CONFIG_DIR: &str = "data/organizations";
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct Organization {
+    // Widths (in pixels) that widths derived from client hints are snapped to
+    pub breakpoints: Vec<u32>,
+    // Used if the request doesn't specify `compression`
+    pub png_compression: PngCompression,
+    // SSIM that `quality=auto` has to reach, between 0 and 1
+    #[serde(deserialize_with = "deserialize_ssim_target")]
+    pub ssim_target: f64,
+    // Used if the request doesn't specify `linear`
+    pub linear_resize: bool,
+    // Background for transparent images encoded as JPEG, used if the request
+    // doesn't specify `bgcolor`
+    pub bgcolor: Color,
+    // Source formats that are transcoded, other sources are rejected
+    pub input_formats: Vec<InputFormat>,
+}
+
+impl Default for Organization {
+    fn default() -> Self {
+        Self {
+            breakpoints: vec![320, 480, 640, 768, 1024, 1280, 1536, 1920, 2560],
+            png_compression: PngCompression::default(),
+            ssim_target: 0.98,
+            linear_resize: false,
+            bgcolor: Color::WHITE,
+            input_formats: InputFormat::ALL.to_vec(),
+        }
+    }
+}
+
+/// Why an organization's settings couldn't be loaded.
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    // The id contains characters that aren't allowed in a settings file name
+    InvalidId,
+    // A settings file that exists but can't be read or parsed
+    InvalidSettings(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidId => write!(f, "invalid organization id"),
+            Self::InvalidSettings(e) => write!(f, "invalid organization settings: {}", e),
+        }
+    }
+}
+
+impl Organization {
+    /// Loads the settings for an organization, falling back to the defaults
+    /// if the organization has no settings file.
+    pub fn load(organization_id: &str) -> Result<Self, LoadError> {
+        Self::load_from(Path::new(CONFIG_DIR), organization_id)
+    }
+
+    fn load_from(dir: &Path, organization_id: &str) -> Result<Self, LoadError> {
+        if !is_valid_id(organization_id) {
+            return Err(LoadError::InvalidId);
+        }
+        let path = dir.join(format!("{}.json", organization_id));
+        let settings = match fs::read_to_string(&path) {
+            Ok(settings) => settings,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(LoadError::InvalidSettings(format!(
+                    "{}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        serde_json::from_str(&settings)
+            .map_err(|e| LoadError::InvalidSettings(format!("{}: {}", path.display(), e)))
+    }
+}
+
+fn deserialize_ssim_target<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let target = f64::deserialize(deserializer)?;
+    if !(0. ..=1.).contains(&target) {
+        return Err(de::Error::custom("ssim_target must be between 0 and 1"));
+    }
+    Ok(target)
+}
+
+// Organization ids end up in a file path, so only allow a safe set of
+// characters.
+fn is_valid_id(organization_id: &str) -> bool {
+    !organization_id.is_empty()
+        && organization_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_id() {
+        assert!(is_valid_id("acme-corp_1"));
+    }
+
+    #[test]
+    fn test_is_valid_id_rejects_paths() {
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id(".."));
+        assert!(!is_valid_id("../etc"));
+    }
+
+    #[test]
+    fn test_load_invalid_id_fails() {
+        // Rather than allowing all input formats by default
+        assert_eq!(Organization::load("acme.corp"), Err(LoadError::InvalidId));
+        assert_eq!(Organization::load("../acme"), Err(LoadError::InvalidId));
+    }
+
+    #[test]
+    fn test_missing_settings_fall_back_to_defaults() {
+        let organization: Organization = serde_json::from_str("{}").unwrap();
+        assert_eq!(organization, Organization::default());
+    }
+
+    #[test]
+    fn test_settings_override_defaults() {
+        let organization: Organization =
+            serde_json::from_str(r#"{"png_compression": "best"}"#).unwrap();
+        assert_eq!(organization.png_compression, PngCompression::Best);
+        assert_eq!(organization.bgcolor, Color::WHITE);
+        assert_eq!(
+            organization.breakpoints,
+            Organization::default().breakpoints
+        );
+    }
+
+    #[test]
+    fn test_load_unknown_organization_yields_defaults() {
+        assert_eq!(
+            Organization::load("unknown").unwrap(),
+            Organization::default()
+        );
+    }
+
+    #[test]
+    fn test_load_invalid_settings_fails() {
+        let dir = std::env::temp_dir().join(format!("imgconv-settings-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Valid input formats, but an invalid PNG compression
+        fs::write(
+            dir.join("acme.json"),
+            r#"{"input_formats": ["jpeg"], "png_compression": "smallest"}"#,
+        )
+        .unwrap();
+        let loaded = Organization::load_from(&dir, "acme");
+        fs::remove_dir_all(&dir).unwrap();
+        // Rather than allowing all input formats by default
+        assert!(loaded.is_err());
+    }
+
+    #[test]
+    fn test_input_formats() {
+        let organization: Organization =
+            serde_json::from_str(r#"{"input_formats": ["jpeg", "png"]}"#).unwrap();
+        assert_eq!(
+            organization.input_formats,
+            vec![InputFormat::Jpeg, InputFormat::Png]
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings_fail() {
+        assert!(serde_json::from_str::<Organization>(r#"{"bgcolor": "white"}"#).is_err());
+    }
+
+    #[test]
+    fn test_ssim_target_out_of_range_fails() {
+        for target in ["-0.5", "1.5"] {
+            let settings = format!(r#"{{"ssim_target": {}}}"#, target);
+            assert!(serde_json::from_str::<Organization>(&settings).is_err());
+        }
+        let organization: Organization = serde_json::from_str(r#"{"ssim_target": 0.95}"#).unwrap();
+        assert_eq!(organization.ssim_target, 0.95);
+    }
+}
@@ -0,0 +1,192 @@
+//! Multi-page sources, such as scanned documents stored as TIFF.
+//!
+//! The `image` crate only decodes the first page (directory) of a TIFF, so
+//! other pages are decoded with the `tiff` crate directly.
+//!
+//! Embedded ICC profiles of TIFFs aren't read, so pages are assumed to be
+//! sRGB, and CMYK pages are converted to RGB naively.
+//!
+//! Bilevel pages, such as scanned faxes, aren't supported: the `tiff` crate
+//! can neither unpack 1-bit samples nor decompress CCITT fax encodings.
+
+use image::{
+    DynamicImage, GrayAlphaImage, GrayImage, ImageBuffer, ImageFormat, Luma, LumaA, Rgb, RgbImage,
+    Rgba, RgbaImage,
+};
+use std::io::Cursor;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
+
+/// The number of pages in `source`, read from the chain of image file
+/// directories without decoding any pixels. Sources other than TIFF have a
+/// single page.
+pub fn page_count(source: &[u8]) -> usize {
+    let Some(mut decoder) = tiff_decoder(source) else {
+        return 1;
+    };
+    let mut count = 1;
+    while decoder.more_images() && decoder.next_image().is_ok() {
+        count += 1;
+    }
+    count
+}
+
+/// Decodes page `page` (counting from 0) of a TIFF source. Returns `None` if
+/// the source isn't a TIFF, the page doesn't exist or its color type isn't
+/// supported.
+pub fn decode_page(source: &[u8], page: usize) -> Option<DynamicImage> {
+    let mut decoder = tiff_decoder(source)?;
+    decoder.seek_to_image(page).ok()?;
+    let (width, height) = decoder.dimensions().ok()?;
+    let color_type = decoder.colortype().ok()?;
+    let image = match (color_type, decoder.read_image().ok()?) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, data)?)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageLumaA8(GrayAlphaImage::from_raw(width, height, data)?)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, data)?)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data)?)
+        }
+        (ColorType::CMYK(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, cmyk_to_rgb(&data))?)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(data)) => {
+            DynamicImage::ImageLuma16(ImageBuffer::<Luma<u16>, _>::from_raw(width, height, data)?)
+        }
+        (ColorType::GrayA(16), DecodingResult::U16(data)) => {
+            DynamicImage::ImageLumaA16(ImageBuffer::<LumaA<u16>, _>::from_raw(width, height, data)?)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(data)) => {
+            DynamicImage::ImageRgb16(ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, data)?)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            DynamicImage::ImageRgba16(ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, data)?)
+        }
+        _ => return None,
+    };
+    Some(image)
+}
+
+// TIFF CMYK samples are ink amounts, 0 meaning no ink
+fn cmyk_to_rgb(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(4)
+        .flat_map(|cmyk| {
+            let white = 255 - cmyk[3] as u32;
+            cmyk[..3]
+                .iter()
+                .map(move |ink| ((255 - *ink as u32) * white / 255) as u8)
+        })
+        .collect()
+}
+
+fn tiff_decoder(source: &[u8]) -> Option<Decoder<Cursor<&[u8]>>> {
+    if image::guess_format(source).ok()? != ImageFormat::Tiff {
+        return None;
+    }
+    Decoder::new(Cursor::new(source)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    // A TIFF with a page per value, each filled with that value
+    fn tiff(values: &[u8]) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+        for value in values {
+            encoder
+                .write_image::<colortype::RGB8>(2, 2, &[*value; 2 * 2 * 3])
+                .unwrap();
+        }
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_page_count() {
+        assert_eq!(page_count(&tiff(&[10, 20, 30])), 3);
+        assert_eq!(page_count(&tiff(&[10])), 1);
+        assert_eq!(page_count(b"\xff\xd8\xff"), 1);
+    }
+
+    #[test]
+    fn test_decode_page() {
+        let source = tiff(&[10, 20, 30]);
+        let page = decode_page(&source, 1).unwrap().to_rgb8();
+        assert_eq!(page.get_pixel(0, 0).0, [20, 20, 20]);
+        assert!(decode_page(&source, 3).is_none());
+    }
+
+    #[test]
+    fn test_decode_page_16_bit() {
+        let mut bytes = Cursor::new(Vec::new());
+        TiffEncoder::new(&mut bytes)
+            .unwrap()
+            .write_image::<colortype::Gray16>(1, 1, &[1000])
+            .unwrap();
+        let page = decode_page(bytes.get_ref(), 0).unwrap();
+        assert_eq!(page.to_luma16().get_pixel(0, 0).0, [1000]);
+    }
+
+    #[test]
+    fn test_decode_page_cmyk() {
+        let mut bytes = Cursor::new(Vec::new());
+        TiffEncoder::new(&mut bytes)
+            .unwrap()
+            .write_image::<colortype::CMYK8>(3, 1, &[255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255])
+            .unwrap();
+        let page = decode_page(bytes.get_ref(), 0).unwrap().to_rgb8();
+        assert_eq!(page.get_pixel(0, 0).0, [0, 255, 255]);
+        assert_eq!(page.get_pixel(1, 0).0, [255, 255, 255]);
+        assert_eq!(page.get_pixel(2, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_page_bilevel_unsupported() {
+        // An uncompressed 8x2 page with 1 bit per sample
+        let mut bytes = b"II*\0\x08\0\0\0".to_vec();
+        let entries: [(u16, u16, u32); 8] = [
+            (256, 3, 8),   // ImageWidth
+            (257, 3, 2),   // ImageLength
+            (258, 3, 1),   // BitsPerSample
+            (259, 3, 1),   // Compression: none
+            (262, 3, 1),   // PhotometricInterpretation: BlackIsZero
+            (273, 4, 110), // StripOffsets
+            (278, 3, 2),   // RowsPerStrip
+            (279, 4, 2),   // StripByteCounts
+        ];
+        bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, field_type, value) in entries {
+            bytes.extend_from_slice(&tag.to_le_bytes());
+            bytes.extend_from_slice(&field_type.to_le_bytes());
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&[0b1010_1010, 0b0101_0101]);
+        assert_eq!(bytes.len(), 112);
+        assert_eq!(page_count(&bytes), 1);
+        assert!(decode_page(&bytes, 0).is_none());
+    }
+
+    #[test]
+    fn test_page_profiles_are_not_read() {
+        let mut bytes = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+        let mut image = encoder.new_image::<colortype::RGB8>(1, 1).unwrap();
+        // The ICC profile tag
+        image
+            .encoder()
+            .write_tag(tiff::tags::Tag::Unknown(34675), &b"profile"[..])
+            .unwrap();
+        image.write_data(&[0; 3]).unwrap();
+        let embedded = crate::metadata::read(bytes.get_ref(), crate::metadata::Metadata::Keep);
+        assert_eq!(embedded.icc_profile, None);
+    }
+}
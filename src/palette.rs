@@ -0,0 +1,137 @@
+//! Color palettes, e.g. to pick a background color matching an image.
+//!
+//! Palettes are extracted with the same quantizer as indexed output, after
+//! which every color is weighed by the share of pixels it represents.
+
+use crate::color::Color;
+use crate::quantize;
+use image::RgbaImage;
+use serde::Serialize;
+use std::cmp::Reverse;
+
+/// Default number of colors in a palette.
+pub const DEFAULT_COLORS: u16 = 5;
+
+/// Largest number of colors in a palette.
+pub const MAX_COLORS: u16 = 16;
+
+/// Largest width and height of the image a palette is extracted from. Larger
+/// images are scaled down first, which hardly affects the result.
+pub const MAX_DIMENSION: u32 = 128;
+
+/// A color of a palette.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Swatch {
+    pub color: Color,
+    // Share of the (visible) pixels closest to this color, between 0 and 1
+    pub proportion: f64,
+}
+
+/// Extracts a palette of at most `colors` colors from `image`, ordered from
+/// the most to the least common color. Fully transparent pixels are ignored,
+/// and partially transparent pixels count by their RGB values only.
+///
+/// # Examples
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use imgconv::color::Color;
+/// use imgconv::palette::palette;
+/// let image = RgbaImage::from_fn(4, 1, |x, _| Rgba([if x == 0 { 0 } else { 255 }, 0, 0, 255]));
+/// let swatches = palette(&image, 5);
+/// assert_eq!(swatches[0].color, Color([255, 0, 0]));
+/// assert_eq!(swatches[0].proportion, 0.75);
+/// ```
+pub fn palette(image: &RgbaImage, colors: u16) -> Vec<Swatch> {
+    // Fully transparent pixels take a palette entry of their own
+    let transparent = image.pixels().any(|p| p[3] == 0) as u16;
+    let indexed = quantize::quantize(image, colors + transparent, false);
+
+    let mut counts = vec![0usize; indexed.palette.len()];
+    for index in &indexed.pixels {
+        counts[*index as usize] += 1;
+    }
+    // The quantizer may yield the same RGB values more than once
+    let mut swatches: Vec<(Color, usize)> = Vec::new();
+    for (entry, count) in indexed.palette.iter().zip(counts) {
+        let color = Color([entry[0], entry[1], entry[2]]);
+        if entry[3] == 0 || count == 0 {
+            continue;
+        }
+        match swatches.iter_mut().find(|(c, _)| *c == color) {
+            Some((_, total)) => *total += count,
+            None => swatches.push((color, count)),
+        }
+    }
+    swatches.sort_by_key(|(_, count)| Reverse(*count));
+    swatches.truncate(colors as usize);
+
+    let visible: usize = swatches.iter().map(|(_, count)| count).sum();
+    swatches
+        .into_iter()
+        .map(|(color, count)| Swatch {
+            color,
+            proportion: count as f64 / visible as f64,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_palette_orders_by_proportion() {
+        // 8 blue, 4 green and 4 red pixels
+        let image = RgbaImage::from_fn(4, 4, |x, y| match (x, y) {
+            (0, _) => Rgba([255, 0, 0, 255]),
+            (1, _) => Rgba([0, 255, 0, 255]),
+            _ => Rgba([0, 0, 255, 255]),
+        });
+        let swatches = palette(&image, 5);
+        assert_eq!(swatches.len(), 3);
+        assert_eq!(swatches[0].color, Color([0, 0, 255]));
+        assert_eq!(swatches[0].proportion, 0.5);
+        assert_eq!(swatches[1].proportion, 0.25);
+    }
+
+    #[test]
+    fn test_palette_limits_colors() {
+        let image = RgbaImage::from_fn(32, 32, |x, y| Rgba([x as u8 * 8, y as u8 * 8, 0, 255]));
+        let swatches = palette(&image, 4);
+        assert!(swatches.len() <= 4);
+        let total: f64 = swatches.iter().map(|s| s.proportion).sum();
+        assert!((total - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_palette_ignores_transparent_pixels() {
+        let image = RgbaImage::from_fn(4, 1, |x, _| {
+            if x == 0 {
+                Rgba([0, 255, 0, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        assert_eq!(
+            palette(&image, 2),
+            vec![Swatch {
+                color: Color([0, 255, 0]),
+                proportion: 1.
+            }]
+        );
+    }
+
+    #[test]
+    fn test_palette_serializes_hex_colors() {
+        let swatch = Swatch {
+            color: Color([255, 128, 0]),
+            proportion: 0.5,
+        };
+        assert_eq!(
+            serde_json::to_string(&swatch).unwrap(),
+            r#"{"color":"ff8000","proportion":0.5}"#
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Placeholders, shown by front-ends while the actual image loads.
+//!
+//! A BlurHash encodes a blurred version of an image in a short string (of 20
+//! to 30 characters, typically), which is decoded into an image client-side.
+//! A low-quality image placeholder (LQIP) is a tiny, blurred image instead,
+//! inlined into HTML as a data URI.
+
+use crate::encode::{self, Options};
+use crate::media_type::MediaType;
+use crate::resize;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::{ImageResult, RgbaImage};
+
+/// Default number of horizontal and vertical BlurHash components. More
+/// components capture more detail, in longer strings.
+pub const DEFAULT_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Largest number of BlurHash components per axis.
+pub const MAX_COMPONENTS: u32 = 9;
+
+/// Largest width and height of the image a placeholder is computed from.
+/// Larger images are scaled down first, as the result only captures its
+/// coarsest features anyway.
+pub const MAX_DIMENSION: u32 = 64;
+
+/// Encodes `image` as a BlurHash of `components_x` by `components_y`
+/// components. Returns `None` if either is outside of 1 to
+/// `MAX_COMPONENTS`.
+///
+/// # Examples
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use imgconv::placeholder::blurhash;
+/// let image = RgbaImage::from_pixel(8, 8, Rgba([255, 255, 255, 255]));
+/// assert_eq!(blurhash(&image, 1, 1).unwrap(), "00TSUA");
+/// ```
+pub fn blurhash(image: &RgbaImage, components_x: u32, components_y: u32) -> Option<String> {
+    let (width, height) = image.dimensions();
+    blurhash::encode(components_x, components_y, width, height, image.as_raw()).ok()
+}
+
+/// Largest width and height of a low-quality image placeholder.
+pub const LQIP_DIMENSION: u32 = 32;
+
+/// Media types a low-quality image placeholder can be encoded as.
+pub const LQIP_MEDIA_TYPES: [MediaType; 2] = [MediaType::JPEG, MediaType::WEBP];
+
+// The blur hides compression artifacts, so a low quality suffices
+const LQIP_QUALITY: u8 = 40;
+const LQIP_SIGMA: f32 = 1.;
+
+/// Blurs `image` and encodes it as a `data:` URI of `media_type`. `image`
+/// should be at most `LQIP_DIMENSION` pixels wide and high, and opaque for
+/// JPEG.
+///
+/// # Examples
+///
+/// ```
+/// use image::RgbaImage;
+/// use imgconv::media_type::MediaType;
+/// use imgconv::placeholder::lqip;
+/// let uri = lqip(&RgbaImage::new(32, 18), &MediaType::WEBP).unwrap();
+/// assert!(uri.starts_with("data:image/webp;base64,"));
+/// ```
+pub fn lqip(image: &RgbaImage, media_type: &MediaType) -> ImageResult<String> {
+    let blurred = resize::blur(image, LQIP_SIGMA);
+    let options = Options {
+        quality: Some(LQIP_QUALITY),
+        ..Options::default()
+    };
+    let bytes = encode::encode(&blurred, media_type, &options)?;
+    Ok(format!(
+        "data:{};base64,{}",
+        media_type.mime_type(),
+        STANDARD.encode(bytes)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_blurhash_length() {
+        // Left half black, right half white
+        let image = RgbaImage::from_fn(32, 24, |x, _| {
+            let v = if x < 16 { 0 } else { 255 };
+            Rgba([v, v, v, 255])
+        });
+        let (x, y) = DEFAULT_COMPONENTS;
+        // A size flag, maximum AC value, DC and 2 characters per AC component
+        assert_eq!(blurhash(&image, x, y).unwrap().len(), 1 + 1 + 4 + 2 * 11);
+    }
+
+    #[test]
+    fn test_blurhash_components_out_of_range() {
+        let image = RgbaImage::new(4, 4);
+        assert!(blurhash(&image, 0, 3).is_none());
+        assert!(blurhash(&image, 4, MAX_COMPONENTS + 1).is_none());
+    }
+
+    #[test]
+    fn test_lqip_decodes_to_blurred_image() {
+        // A single white pixel on black
+        let mut image = RgbaImage::from_pixel(32, 32, Rgba([0, 0, 0, 255]));
+        image.put_pixel(16, 16, Rgba([255, 255, 255, 255]));
+        let uri = lqip(&image, &MediaType::JPEG).unwrap();
+        let encoded = uri.strip_prefix("data:image/jpeg;base64,").unwrap();
+        let decoded = image::load_from_memory(&STANDARD.decode(encoded).unwrap())
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(decoded.dimensions(), (32, 32));
+        assert!(decoded.get_pixel(16, 16)[0] < 128);
+        assert!(decoded.get_pixel(17, 16)[0] > 0);
+    }
+
+    #[test]
+    fn test_lqip_transparent_border_has_no_fringes() {
+        // Red with a transparent black border
+        let image = RgbaImage::from_fn(32, 32, |x, y| {
+            if (4..28).contains(&x) && (4..28).contains(&y) {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let uri = lqip(&image, &MediaType::WEBP).unwrap();
+        let encoded = uri.strip_prefix("data:image/webp;base64,").unwrap();
+        let decoded = image::load_from_memory(&STANDARD.decode(encoded).unwrap())
+            .unwrap()
+            .to_rgba8();
+        // The edge of the red area fades out, rather than darkening
+        let edge = decoded.get_pixel(4, 16);
+        assert!(edge[3] < 255 && edge[0] > 200, "{:?}", edge);
+    }
+}
@@ -0,0 +1,236 @@
+//! Palette quantization, for indexed (palette based) output such as PNG8.
+
+use color_quant::NeuQuant;
+use image::{imageops, Rgba, RgbaImage};
+use std::collections::HashMap;
+
+pub const MIN_COLORS: u16 = 2;
+pub const MAX_COLORS: u16 = 256;
+
+// NeuQuant sampling factor, from 1 (best quality, slowest) to 30
+const SAMPLE_FACTOR: i32 = 10;
+
+/// An image as indices into a palette of RGBA colors.
+#[derive(Debug)]
+pub struct Indexed {
+    pub width: u32,
+    pub height: u32,
+    // Palette entries that aren't fully opaque come first, so that the alpha
+    // values can be written as a short tRNS chunk.
+    pub palette: Vec<[u8; 4]>,
+    pub pixels: Vec<u8>,
+}
+
+impl Indexed {
+    /// The palette's RGB values, as expected by the PNG `PLTE` chunk.
+    pub fn rgb_palette(&self) -> Vec<u8> {
+        self.palette
+            .iter()
+            .flat_map(|c| [c[0], c[1], c[2]])
+            .collect()
+    }
+
+    /// The palette's alpha values up to and including the last transparent
+    /// entry, as expected by the PNG `tRNS` chunk.
+    pub fn transparency(&self) -> Vec<u8> {
+        let len = self
+            .palette
+            .iter()
+            .rposition(|c| c[3] < u8::MAX)
+            .map_or(0, |i| i + 1);
+        self.palette[..len].iter().map(|c| c[3]).collect()
+    }
+
+    /// The smallest PNG bit depth (1, 2, 4 or 8) that can index the palette.
+    pub fn bit_depth(&self) -> u8 {
+        match self.palette.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        }
+    }
+
+    /// The pixels packed at `bit_depth`, with every row starting on a byte
+    /// boundary.
+    pub fn packed_pixels(&self) -> Vec<u8> {
+        let depth = self.bit_depth() as usize;
+        if depth == 8 {
+            return self.pixels.clone();
+        }
+        let per_byte = 8 / depth;
+        self.pixels
+            .chunks(self.width as usize)
+            .flat_map(|row| {
+                row.chunks(per_byte).map(|chunk| {
+                    chunk.iter().enumerate().fold(0u8, |byte, (i, index)| {
+                        byte | index << (8 - depth * (i + 1))
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+/// Reduces `image` to at most `colors` colors, optionally applying
+/// Floyd–Steinberg dithering.
+///
+/// Fully transparent pixels get a palette entry of their own, as the quantizer
+/// tends to merge the (often few) transparent pixels of e.g. a logo with
+/// their surroundings. Images that already have few enough colors keep their
+/// exact colors.
+pub fn quantize(image: &RgbaImage, colors: u16, dither: bool) -> Indexed {
+    let colors = colors.clamp(MIN_COLORS, MAX_COLORS) as usize;
+    let is_transparent = |p: &Rgba<u8>| p[3] == 0;
+
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    if image.pixels().any(is_transparent) {
+        palette.push([0, 0, 0, 0]);
+    }
+    let offset = palette.len();
+    let slots = colors - offset;
+
+    let mut unique: HashMap<[u8; 4], usize> = HashMap::new();
+    for p in image.pixels().filter(|p| !is_transparent(p)) {
+        if unique.len() > slots {
+            break;
+        }
+        let next = unique.len();
+        unique.entry(p.0).or_insert(next);
+    }
+
+    let mut mapped = image.clone();
+    let quantizer = if unique.len() <= slots {
+        let mut exact: Vec<_> = unique.iter().collect();
+        exact.sort_by_key(|(_, i)| **i);
+        palette.extend(exact.into_iter().map(|(color, _)| *color));
+        None
+    } else {
+        let visible: Vec<u8> = image
+            .pixels()
+            .filter(|p| !is_transparent(p))
+            .flat_map(|p| p.0)
+            .collect();
+        let quantizer = NeuQuant::new(SAMPLE_FACTOR, slots, &visible);
+        if dither {
+            imageops::dither(&mut mapped, &quantizer);
+        }
+        palette.extend(
+            quantizer
+                .color_map_rgba()
+                .chunks_exact(4)
+                .map(|c| [c[0], c[1], c[2], c[3]]),
+        );
+        Some(quantizer)
+    };
+
+    // Move transparent entries to the front of the palette
+    let mut order: Vec<usize> = (0..palette.len()).collect();
+    order.sort_by_key(|i| palette[*i][3] == u8::MAX);
+    let mut remap = vec![0u8; palette.len()];
+    for (new, old) in order.iter().enumerate() {
+        remap[*old] = new as u8;
+    }
+
+    let pixels = image
+        .pixels()
+        .zip(mapped.pixels())
+        .map(|(original, mapped)| {
+            if is_transparent(original) {
+                return remap[0];
+            }
+            let index = match &quantizer {
+                Some(quantizer) => quantizer.index_of(&mapped.0),
+                None => unique[&original.0],
+            };
+            remap[offset + index]
+        })
+        .collect();
+
+    Indexed {
+        width: image.width(),
+        height: image.height(),
+        palette: order.iter().map(|i| palette[*i]).collect(),
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_colors() -> RgbaImage {
+        RgbaImage::from_fn(8, 2, |x, _| {
+            if x < 4 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn test_quantize_keeps_exact_colors() {
+        let image = RgbaImage::from_fn(4, 4, |x, _| Rgba([x as u8 * 10, 0, 0, 255]));
+        let indexed = quantize(&image, 4, true);
+        assert_eq!(
+            indexed.palette,
+            vec![
+                [0, 0, 0, 255],
+                [10, 0, 0, 255],
+                [20, 0, 0, 255],
+                [30, 0, 0, 255]
+            ]
+        );
+        assert_eq!(&indexed.pixels[..4], &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_quantize_limits_colors() {
+        let image = RgbaImage::from_fn(32, 32, |x, y| Rgba([x as u8 * 8, y as u8 * 8, 0, 255]));
+        let indexed = quantize(&image, 16, true);
+        assert!(indexed.palette.len() <= 16);
+        assert_eq!(indexed.pixels.len(), 32 * 32);
+    }
+
+    #[test]
+    fn test_quantize_puts_transparent_entries_first() {
+        let indexed = quantize(&two_colors(), 2, false);
+        assert_eq!(indexed.palette[0][3], 0);
+        assert_eq!(indexed.transparency(), vec![0]);
+    }
+
+    #[test]
+    fn test_quantize_keeps_single_transparent_pixel() {
+        let mut image = RgbaImage::from_pixel(8, 8, Rgba([255, 0, 0, 255]));
+        image.put_pixel(3, 3, Rgba([255, 0, 0, 0]));
+        let indexed = quantize(&image, 4, true);
+        let index = indexed.pixels[3 * 8 + 3] as usize;
+        assert_eq!(indexed.palette[index][3], 0);
+    }
+
+    #[test]
+    fn test_quantize_fully_transparent_image() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 0]));
+        let indexed = quantize(&image, 16, false);
+        assert_eq!(indexed.palette, vec![[0, 0, 0, 0]]);
+        assert!(indexed.pixels.iter().all(|i| *i == 0));
+    }
+
+    #[test]
+    fn test_bit_depth() {
+        let indexed = quantize(&two_colors(), 2, false);
+        assert_eq!(indexed.bit_depth(), 1);
+    }
+
+    #[test]
+    fn test_packed_pixels() {
+        let indexed = Indexed {
+            width: 3,
+            height: 2,
+            palette: vec![[0, 0, 0, 0], [255, 255, 255, 255]],
+            pixels: vec![1, 0, 1, 0, 1, 1],
+        };
+        assert_eq!(indexed.packed_pixels(), vec![0b1010_0000, 0b0110_0000]);
+    }
+}
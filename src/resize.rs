@@ -0,0 +1,232 @@
+//! Resizing.
+//!
+//! Resizing gamma-encoded sRGB values darkens fine detail and high-contrast
+//! edges when downscaling. Resizing in linear light avoids this, at the cost of
+//! converting every pixel to floating point and back.
+//!
+//! Images with an alpha channel are resized (and blurred) with premultiplied
+//! alpha, as the colors of (fully) transparent pixels would otherwise bleed
+//! into their neighbours, e.g. giving transparent logos dark fringes.
+//!
+//! Sources with 16 bits per channel (or floating point channels) can be
+//! resized to 16-bit images, for media types that can store them.
+
+use image::{imageops, DynamicImage, ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
+
+/// An RGBA image with 16 bits per channel.
+pub type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+const FILTER: imageops::FilterType = imageops::FilterType::CatmullRom;
+
+/// Resizes `image` to exactly `width` x `height`, optionally in linear light.
+/// Images with an alpha channel are always resized with premultiplied alpha.
+///
+/// # Examples
+///
+/// ```
+/// use image::{DynamicImage, RgbaImage};
+/// use imgconv::resize::resize;
+/// let image = DynamicImage::ImageRgba8(RgbaImage::new(40, 20));
+/// assert_eq!(resize(&image, 10, 5, true).dimensions(), (10, 5));
+/// ```
+pub fn resize(image: &DynamicImage, width: u32, height: u32, linear: bool) -> RgbaImage {
+    if !linear && !image.color().has_alpha() {
+        return imageops::resize(image, width, height, FILTER);
+    }
+    DynamicImage::ImageRgba32F(resize_f32(image, width, height, linear)).to_rgba8()
+}
+
+/// Resizes `image` like `resize`, keeping 16 bits per channel.
+///
+/// # Examples
+///
+/// ```
+/// use image::{DynamicImage, ImageBuffer, Luma};
+/// use imgconv::resize::resize16;
+/// let image = DynamicImage::ImageLuma16(ImageBuffer::from_pixel(40, 20, Luma([1000u16])));
+/// assert_eq!(resize16(&image, 10, 5, false).get_pixel(5, 2).0, [1000, 1000, 1000, 65535]);
+/// ```
+pub fn resize16(image: &DynamicImage, width: u32, height: u32, linear: bool) -> Rgba16Image {
+    if !linear && !image.color().has_alpha() {
+        return imageops::resize(&image.to_rgba16(), width, height, FILTER);
+    }
+    DynamicImage::ImageRgba32F(resize_f32(image, width, height, linear)).to_rgba16()
+}
+
+/// Whether `image` has more than 8 bits per channel.
+pub fn is_high_bit_depth(image: &DynamicImage) -> bool {
+    let color = image.color();
+    color.bytes_per_pixel() > color.channel_count()
+}
+
+/// Applies a Gaussian blur with standard deviation `sigma` to `image`, with
+/// premultiplied alpha.
+pub fn blur(image: &RgbaImage, sigma: f32) -> RgbaImage {
+    let mut image = DynamicImage::ImageRgba8(image.clone()).into_rgba32f();
+    image.pixels_mut().for_each(premultiply);
+    let mut blurred = imageops::blur(&image, sigma);
+    blurred.pixels_mut().for_each(unpremultiply);
+    DynamicImage::ImageRgba32F(blurred).to_rgba8()
+}
+
+fn resize_f32(image: &DynamicImage, width: u32, height: u32, linear: bool) -> Rgba32FImage {
+    let premultiplied = image.color().has_alpha();
+    let mut image = image.to_rgba32f();
+    for pixel in image.pixels_mut() {
+        if linear {
+            map_rgb(pixel, to_linear);
+        }
+        if premultiplied {
+            premultiply(pixel);
+        }
+    }
+    let mut resized: Rgba32FImage = imageops::resize(&image, width, height, FILTER);
+    for pixel in resized.pixels_mut() {
+        if premultiplied {
+            unpremultiply(pixel);
+        }
+        if linear {
+            map_rgb(pixel, to_srgb);
+        }
+    }
+    resized
+}
+
+fn map_rgb(pixel: &mut Rgba<f32>, f: fn(f32) -> f32) {
+    for channel in &mut pixel.0[..3] {
+        *channel = f(channel.clamp(0., 1.));
+    }
+}
+
+fn premultiply(pixel: &mut Rgba<f32>) {
+    let alpha = pixel[3];
+    for channel in &mut pixel.0[..3] {
+        *channel *= alpha;
+    }
+}
+
+fn unpremultiply(pixel: &mut Rgba<f32>) {
+    let alpha = pixel[3];
+    for channel in &mut pixel.0[..3] {
+        *channel = if alpha > 0. {
+            (*channel / alpha).min(1.)
+        } else {
+            0.
+        };
+    }
+}
+
+// The sRGB transfer functions, on values between 0 and 1
+fn to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Alternating black and white columns
+    fn stripes() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| {
+            let v = if x % 2 == 0 { 0 } else { 255 };
+            Rgba([v, v, v, 255])
+        }))
+    }
+
+    #[test]
+    fn test_transfer_functions_round_trip() {
+        for v in [0., 0.002, 0.04, 0.5, 1.] {
+            assert!((to_srgb(to_linear(v)) - v).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_resize_gamma_encoded_darkens_stripes() {
+        let resized = resize(&stripes(), 16, 16, false);
+        let v = resized.get_pixel(8, 8)[0];
+        assert!((120..=135).contains(&v));
+    }
+
+    #[test]
+    fn test_resize_linear_keeps_brightness() {
+        // Half of the light of white is sRGB 188
+        let resized = resize(&stripes(), 16, 16, true);
+        let v = resized.get_pixel(8, 8)[0];
+        assert!((183..=193).contains(&v));
+    }
+
+    #[test]
+    fn test_resize_linear_keeps_solid_colors() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([10, 100, 200, 50])));
+        let resized = resize(&image, 3, 3, true);
+        assert_eq!(resized.get_pixel(1, 1).0, [10, 100, 200, 50]);
+    }
+
+    #[test]
+    fn test_resize16_keeps_precision() {
+        // A gradient too subtle for 8 bits per channel
+        let image = DynamicImage::ImageRgba16(ImageBuffer::from_fn(64, 1, |x, _| {
+            Rgba([1000 + x as u16, 0, 0, 65535])
+        }));
+        for linear in [false, true] {
+            let resized = resize16(&image, 16, 1, linear);
+            let first = resized.get_pixel(0, 0)[0];
+            let last = resized.get_pixel(15, 0)[0];
+            assert!(last - first > 50, "{} {}", first, last);
+        }
+    }
+
+    #[test]
+    fn test_is_high_bit_depth() {
+        assert!(!is_high_bit_depth(&stripes()));
+        assert!(is_high_bit_depth(&DynamicImage::new_luma16(1, 1)));
+        assert!(is_high_bit_depth(&DynamicImage::new_rgb32f(1, 1)));
+    }
+
+    #[test]
+    fn test_resize_premultiplied_prevents_dark_fringes() {
+        // White on transparent black
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, _| {
+            if x < 32 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        }));
+        for linear in [false, true] {
+            let resized = resize(&image, 16, 16, linear);
+            for pixel in resized.pixels().filter(|p| p[3] > 0) {
+                assert!(pixel[0] >= 250, "{:?}", pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_blur_premultiplied_prevents_dark_fringes() {
+        // White with a transparent black border
+        let image = RgbaImage::from_fn(32, 32, |x, y| {
+            if (8..24).contains(&x) && (8..24).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let blurred = blur(&image, 2.);
+        assert!(blurred.get_pixel(7, 16)[3] > 0);
+        for pixel in blurred.pixels().filter(|p| p[3] > 0) {
+            assert!(pixel[0] >= 250, "{:?}", pixel);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! The RIFF container that WebP images are stored in.
+//!
+//! A RIFF file starts with a 12 byte header (`RIFF`, the file size and the
+//! form type, e.g. `WEBP`), followed by chunks. Every chunk has a four
+//! character code, a 32 bit little-endian payload size and the payload, which
+//! is padded to an even size.
+
+use std::ops::Range;
+
+const HEADER_SIZE: usize = 12;
+
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    pub fourcc: [u8; 4],
+    // Position of the payload in the file, cut short if the file is truncated
+    pub payload: Range<usize>,
+}
+
+/// Iterates over the top-level chunks of a RIFF file.
+///
+/// # Examples
+///
+/// ```
+/// use imgconv::riff::chunks;
+/// let webp = b"RIFF\x0e\0\0\0WEBPVP8L\x01\0\0\0\x2f\0";
+/// let chunk = chunks(webp).next().unwrap();
+/// assert_eq!(&chunk.fourcc, b"VP8L");
+/// assert_eq!(chunk.payload, 20..21);
+/// ```
+pub fn chunks(riff: &[u8]) -> impl Iterator<Item = Chunk> + '_ {
+    let mut offset = HEADER_SIZE;
+    std::iter::from_fn(move || {
+        let header = riff.get(offset..offset + 8)?;
+        let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let start = offset + 8;
+        offset = start + size + size % 2;
+        Some(Chunk {
+            fourcc: header[..4].try_into().unwrap(),
+            payload: start..(start + size).min(riff.len()),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_skip_padding() {
+        let riff = b"RIFF\0\0\0\0WEBPABCD\x01\0\0\0x\0EFGH\x02\0\0\0yz";
+        let fourccs: Vec<[u8; 4]> = chunks(riff).map(|chunk| chunk.fourcc).collect();
+        assert_eq!(fourccs, vec![*b"ABCD", *b"EFGH"]);
+        assert_eq!(chunks(riff).nth(1).unwrap().payload, 30..32);
+    }
+
+    #[test]
+    fn test_chunks_truncated() {
+        let riff = b"RIFF\0\0\0\0WEBPABCD\x10\0\0\0xy";
+        assert_eq!(chunks(riff).next().unwrap().payload, 20..22);
+        assert_eq!(chunks(b"RIFF").count(), 0);
+    }
+}
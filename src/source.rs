@@ -0,0 +1,128 @@
+//! Source images.
+//!
+//! The format of a source is detected from its contents (magic bytes) rather
+//! than its file name, and organizations can restrict which formats they
+//! accept. SVG sources are handled by the `svg` module.
+
+use crate::svg;
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, ImageFormat, ImageResult};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+    Tiff,
+    Bmp,
+    Qoi,
+    Svg,
+}
+
+impl InputFormat {
+    /// All supported input formats.
+    pub const ALL: [Self; 8] = [
+        Self::Jpeg,
+        Self::Png,
+        Self::Webp,
+        Self::Gif,
+        Self::Tiff,
+        Self::Bmp,
+        Self::Qoi,
+        Self::Svg,
+    ];
+
+    fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Jpeg => Some(Self::Jpeg),
+            ImageFormat::Png => Some(Self::Png),
+            ImageFormat::WebP => Some(Self::Webp),
+            ImageFormat::Gif => Some(Self::Gif),
+            ImageFormat::Tiff => Some(Self::Tiff),
+            ImageFormat::Bmp => Some(Self::Bmp),
+            ImageFormat::Qoi => Some(Self::Qoi),
+            _ => None,
+        }
+    }
+}
+
+/// Detects the format of `source` from its magic bytes. Returns `None` for
+/// unknown and unsupported formats.
+///
+/// # Examples
+///
+/// ```
+/// use imgconv::source::{detect, InputFormat};
+/// assert_eq!(detect(b"\x89PNG\r\n\x1a\n"), Some(InputFormat::Png));
+/// assert_eq!(detect(b"<html>"), None);
+/// ```
+pub fn detect(source: &[u8]) -> Option<InputFormat> {
+    if svg::is_svg(source) {
+        return Some(InputFormat::Svg);
+    }
+    reader(source)
+        .format()
+        .and_then(InputFormat::from_image_format)
+}
+
+/// Decodes a raster `source`, detecting its format from its magic bytes.
+pub fn decode(source: &[u8]) -> ImageResult<DynamicImage> {
+    reader(source).decode()
+}
+
+/// The dimensions of a raster `source`, read from its header without decoding
+/// the pixels.
+pub fn dimensions(source: &[u8]) -> ImageResult<(u32, u32)> {
+    reader(source).into_dimensions()
+}
+
+fn reader(source: &[u8]) -> ImageReader<Cursor<&[u8]>> {
+    // Reading from memory can't fail
+    ImageReader::new(Cursor::new(source))
+        .with_guessed_format()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    fn encoded(format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::new(2, 2))
+            .write_to(&mut Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(&encoded(ImageFormat::Gif)), Some(InputFormat::Gif));
+        assert_eq!(detect(&encoded(ImageFormat::Tiff)), Some(InputFormat::Tiff));
+        assert_eq!(detect(&encoded(ImageFormat::Qoi)), Some(InputFormat::Qoi));
+        assert_eq!(detect(b"<svg/>"), Some(InputFormat::Svg));
+    }
+
+    #[test]
+    fn test_detect_unsupported_format() {
+        assert_eq!(detect(&encoded(ImageFormat::Ico)), None);
+        assert_eq!(detect(b""), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        let image = decode(&encoded(ImageFormat::Bmp)).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 2));
+    }
+
+    #[test]
+    fn test_dimensions() {
+        assert_eq!(dimensions(&encoded(ImageFormat::Png)).unwrap(), (2, 2));
+        assert!(dimensions(b"").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Structural similarity (SSIM), a perceptual metric for how closely an
+//! encoded image resembles its source.
+//!
+//! SSIM is computed on luma only, over 8x8 windows with a stride of 4 pixels,
+//! and averaged over all windows. 1 means identical.
+
+use image::{Rgba, RgbaImage};
+
+const WINDOW: u32 = 8;
+const STRIDE: u32 = 4;
+
+// Stabilizing constants, for a dynamic range of 255
+const C1: f64 = (0.01 * 255.) * (0.01 * 255.);
+const C2: f64 = (0.03 * 255.) * (0.03 * 255.);
+
+/// Computes the mean SSIM of two images of equal dimensions.
+///
+/// # Examples
+///
+/// ```
+/// use image::{Rgba, RgbaImage};
+/// use imgconv::ssim::ssim;
+/// let image = RgbaImage::from_fn(16, 16, |x, _| Rgba([x as u8 * 16, 0, 0, 255]));
+/// assert_eq!(ssim(&image, &image), 1.);
+/// ```
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> f64 {
+    assert_eq!(a.dimensions(), b.dimensions());
+    let (width, height) = a.dimensions();
+    let (a, b) = (luma(a), luma(b));
+    let window_w = WINDOW.min(width);
+    let window_h = WINDOW.min(height);
+
+    let mut sum = 0.;
+    let mut count = 0;
+    for y in (0..=height.saturating_sub(window_h)).step_by(STRIDE as usize) {
+        for x in (0..=width.saturating_sub(window_w)).step_by(STRIDE as usize) {
+            let pixels = (y..y + window_h)
+                .flat_map(|y| (x..x + window_w).map(move |x| (y * width + x) as usize));
+            sum += window_ssim(pixels.map(|i| (a[i], b[i])));
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return 1.;
+    }
+    sum / count as f64
+}
+
+fn window_ssim(pixels: impl Iterator<Item = (f64, f64)> + Clone) -> f64 {
+    let n = pixels.clone().count() as f64;
+    let (sum_a, sum_b) = pixels
+        .clone()
+        .fold((0., 0.), |(sa, sb), (a, b)| (sa + a, sb + b));
+    let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+    let (var_a, var_b, covar) = pixels.fold((0., 0., 0.), |(va, vb, cv), (a, b)| {
+        let (da, db) = (a - mean_a, b - mean_b);
+        (va + da * da, vb + db * db, cv + da * db)
+    });
+    let (var_a, var_b, covar) = (var_a / n, var_b / n, covar / n);
+    ((2. * mean_a * mean_b + C1) * (2. * covar + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
+
+// Rec. 601 luma, with transparent pixels treated as black
+fn luma(image: &RgbaImage) -> Vec<f64> {
+    image
+        .pixels()
+        .map(|Rgba([r, g, b, a])| {
+            let alpha = *a as f64 / 255.;
+            (0.299 * *r as f64 + 0.587 * *g as f64 + 0.114 * *b as f64) * alpha
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(32, 32, |x, y| {
+            Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255])
+        })
+    }
+
+    #[test]
+    fn test_ssim_identical() {
+        assert_eq!(ssim(&gradient(), &gradient()), 1.);
+    }
+
+    #[test]
+    fn test_ssim_degrades_with_noise() {
+        let noisy = |amount: i32| {
+            RgbaImage::from_fn(32, 32, |x, y| {
+                let p = gradient().get_pixel(x, y).0;
+                let n = if (x + y) % 2 == 0 { amount } else { -amount };
+                let c = |v: u8| (v as i32 + n).clamp(0, 255) as u8;
+                Rgba([c(p[0]), c(p[1]), c(p[2]), 255])
+            })
+        };
+        let slight = ssim(&gradient(), &noisy(4));
+        let heavy = ssim(&gradient(), &noisy(40));
+        assert!(slight < 1.);
+        assert!(heavy < slight);
+    }
+
+    #[test]
+    fn test_ssim_image_smaller_than_window() {
+        let image = RgbaImage::from_pixel(3, 2, Rgba([10, 20, 30, 255]));
+        assert_eq!(ssim(&image, &image), 1.);
+    }
+}